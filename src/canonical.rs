@@ -0,0 +1,199 @@
+use reqwest::Url;
+
+use serde::{Deserialize, Serialize};
+
+use std::str::FromStr;
+
+// What to do with the trailing slash of a path.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum TrailingSlash {
+    Keep,
+    Strip,
+    Add,
+}
+
+impl FromStr for TrailingSlash {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "keep" => Ok(TrailingSlash::Keep),
+            "strip" => Ok(TrailingSlash::Strip),
+            "add" => Ok(TrailingSlash::Add),
+            _ => Err(()),
+        }
+    }
+}
+
+// Rules applied to turn the different spellings of a url into a single one.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct CanonicalOptions {
+    pub trailing_slash: TrailingSlash,
+    pub sort_query: bool,
+    pub ignored_params: Vec<String>,
+}
+
+impl Default for CanonicalOptions {
+    fn default() -> Self {
+        CanonicalOptions {
+            trailing_slash: TrailingSlash::Keep,
+            sort_query: true,
+            ignored_params: vec![],
+        }
+    }
+}
+
+// Returns the canonical form of a url, used to recognize pages that were already visited.
+// Parsing the url already lowercases its scheme and host, drops the default port and
+// removes the dot-segments. The fragment is removed, the percent-encoding normalized,
+// and the trailing slash and the query parameters follow the options.
+pub fn canonicalize(url: &Url, options: &CanonicalOptions) -> Url {
+    let mut canonical = url.clone();
+    canonical.set_fragment(None);
+    if canonical.cannot_be_a_base() {
+        return canonical;
+    }
+
+    let mut path = normalize_percent_encoding(canonical.path());
+    match options.trailing_slash {
+        TrailingSlash::Strip if path.len() > 1 && path.ends_with('/') => {
+            path.pop();
+        }
+        // Only adds a slash to paths that do not look like files.
+        TrailingSlash::Add
+            if !path.ends_with('/') && !path.rsplit('/').next().unwrap_or("").contains('.') =>
+        {
+            path.push('/');
+        }
+        _ => (),
+    }
+    canonical.set_path(&path);
+
+    let query = canonical.query().map(|query| {
+        let mut params: Vec<String> = query
+            .split('&')
+            .filter(|param| !param.is_empty())
+            .filter(|param| {
+                let name = param.split('=').next().unwrap_or("");
+                !options
+                    .ignored_params
+                    .iter()
+                    .any(|ignored| matches_param(ignored, name))
+            })
+            .map(normalize_percent_encoding)
+            .collect();
+        if options.sort_query {
+            params.sort();
+        }
+        params.join("&")
+    });
+    canonical.set_query(query.as_deref().filter(|query| !query.is_empty()));
+    canonical
+}
+
+// Checks a parameter name against an ignored name, which may end with '*' to ignore
+// every parameter starting with it.
+fn matches_param(ignored: &str, name: &str) -> bool {
+    match ignored.strip_suffix('*') {
+        Some(prefix) => name.starts_with(prefix),
+        None => ignored == name,
+    }
+}
+
+// Decodes the percent-encoded characters that do not need to be encoded, and writes
+// the remaining escapes in uppercase.
+fn normalize_percent_encoding(input: &str) -> String {
+    let bytes = input.as_bytes();
+    let mut output = String::with_capacity(input.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        let escape = match (bytes[index], bytes.get(index + 1), bytes.get(index + 2)) {
+            (b'%', Some(high), Some(low))
+                if high.is_ascii_hexdigit() && low.is_ascii_hexdigit() =>
+            {
+                u8::from_str_radix(&input[index + 1..index + 3], 16).ok()
+            }
+            _ => None,
+        };
+        match escape {
+            Some(byte) if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) => {
+                output.push(byte as char);
+                index += 3;
+            }
+            Some(_) => {
+                output.push_str(&input[index..index + 3].to_uppercase());
+                index += 3;
+            }
+            None => {
+                output.push(bytes[index] as char);
+                index += 1;
+            }
+        }
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn canonical(url: &str, options: &CanonicalOptions) -> String {
+        canonicalize(&Url::parse(url).unwrap(), options).into_string()
+    }
+
+    #[test]
+    fn test_canonicalization() {
+        let options = CanonicalOptions::default();
+        assert_eq!(
+            canonical("https://docs.rs/a#top", &options),
+            "https://docs.rs/a"
+        );
+        assert_eq!(
+            canonical("HTTPS://Docs.RS:443/a/./b/../c", &options),
+            "https://docs.rs/a/c"
+        );
+        assert_eq!(
+            canonical("https://docs.rs/%7euser/%2Fpath%2f%41", &options),
+            "https://docs.rs/~user/%2Fpath%2FA"
+        );
+        assert_eq!(
+            canonical("https://docs.rs/search?q=rust&lang=en", &options),
+            canonical("https://docs.rs/search?lang=en&q=rust", &options)
+        );
+        assert_eq!(
+            canonical("https://docs.rs/a?", &options),
+            "https://docs.rs/a"
+        );
+        assert_eq!(
+            canonical("https://docs.rs/a/", &options),
+            "https://docs.rs/a/"
+        );
+    }
+
+    #[test]
+    fn test_canonicalization_options() {
+        let strip = CanonicalOptions {
+            trailing_slash: TrailingSlash::Strip,
+            sort_query: false,
+            ignored_params: vec![String::from("utm_*"), String::from("sid")],
+        };
+        assert_eq!(canonical("https://docs.rs/a/", &strip), "https://docs.rs/a");
+        assert_eq!(canonical("https://docs.rs/", &strip), "https://docs.rs/");
+        assert_eq!(
+            canonical("https://docs.rs/a?utm_source=x&q=2&sid=3&b=1", &strip),
+            "https://docs.rs/a?q=2&b=1"
+        );
+
+        let add = CanonicalOptions {
+            trailing_slash: TrailingSlash::Add,
+            ..Default::default()
+        };
+        assert_eq!(canonical("https://docs.rs/a", &add), "https://docs.rs/a/");
+        assert_eq!(
+            canonical("https://docs.rs/a/index.html", &add),
+            "https://docs.rs/a/index.html"
+        );
+    }
+}
@@ -0,0 +1,199 @@
+use crate::crawler::crawl;
+use crate::domain::Domain;
+use crate::error::{CrawlError, ErrorType};
+use crate::export::{export, ExportFormat};
+use crate::json::{BrokenLinksJson, GraphJson, UrlsJson};
+use crate::options::CrawlOptions;
+use crate::storage::SharedStorage;
+use crate::Result;
+
+use url::form_urlencoded;
+
+// Exit codes of the command line.
+const EXIT_OK: i32 = 0;
+const EXIT_ERROR: i32 = 1;
+const EXIT_BROKEN_LINKS: i32 = 2;
+
+const USAGE: &str = "usage: crawler crawl <url> [--fail-on-broken] [--<option>=<value>...]
+       crawler export <url> [--format=json|dot|graphml]
+options: the query parameters of POST /crawl, e.g. --max-depth=3 --sitemaps=false";
+
+// Runs the command given on the command line instead of the server, and returns the exit code.
+// `crawl` crawls a domain and prints its urls, then its broken links if there are any. With
+// --fail-on-broken, the exit code tells if broken links were found. `export` prints the link
+// graph of a crawled domain.
+pub fn run(args: &[String], storage: &SharedStorage) -> i32 {
+    let result = match args.first().map(String::as_str) {
+        Some("crawl") => run_crawl(&args[1..], storage),
+        Some("export") => run_export(&args[1..], storage),
+        _ => {
+            eprintln!("{}", USAGE);
+            return EXIT_ERROR;
+        }
+    };
+    result.unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        EXIT_ERROR
+    })
+}
+
+fn run_crawl(args: &[String], storage: &SharedStorage) -> Result<i32> {
+    let (url, fail_on_broken, query) = parse_crawl_args(args)?;
+    let domain = Domain::new(url)?;
+    let options = CrawlOptions::from_query(Some(&query))?;
+    options.validate()?;
+    let json = crawl(storage, &domain, &options, None, &mut |progress| {
+        eprintln!(
+            "depth {}: {} urls visited, {} queued",
+            progress.depth, progress.visited_urls, progress.queued_urls
+        )
+    })?;
+    println!("{}", json);
+    Ok(report_broken_links(&json, fail_on_broken))
+}
+
+// Reads the arguments of the crawl command: the url, whether to fail on broken links, and
+// the options as a query string.
+fn parse_crawl_args(args: &[String]) -> Result<(&str, bool, String)> {
+    let mut url = None;
+    let mut fail_on_broken = false;
+    let mut query = form_urlencoded::Serializer::new(String::new());
+    // The command line crawls the domain again instead of reading a stored result, unless
+    // --force-refresh=false comes after and overrides it.
+    query.append_pair("force_refresh", "true");
+    for arg in args {
+        match arg.as_str() {
+            "--fail-on-broken" => fail_on_broken = true,
+            // Options are written like the query parameters, with dashes instead of underscores.
+            option if option.starts_with("--") => {
+                let (name, value) = option[2..]
+                    .split_once('=')
+                    .ok_or_else(|| CrawlError::new(ErrorType::InvalidOption))?;
+                query.append_pair(&name.replace('-', "_"), value);
+            }
+            candidate if url.is_none() => url = Some(candidate),
+            _ => return Err(CrawlError::new(ErrorType::InvalidOption)),
+        }
+    }
+    let url = url.ok_or_else(|| CrawlError::new(ErrorType::MissingParameter))?;
+    Ok((url, fail_on_broken, query.finish()))
+}
+
+// Prints the broken links of a crawl, if any, and returns the exit code of the command.
+fn report_broken_links(json: &UrlsJson, fail_on_broken: bool) -> i32 {
+    let broken_links = BrokenLinksJson::new(&json.domain_crawled, &json.urls);
+    if broken_links.nb_broken_links == 0 {
+        return EXIT_OK;
+    }
+    eprintln!("{}", broken_links);
+    if fail_on_broken {
+        EXIT_BROKEN_LINKS
+    } else {
+        EXIT_OK
+    }
+}
+
+fn run_export(args: &[String], storage: &SharedStorage) -> Result<i32> {
+    let mut url = None;
+    let mut format = ExportFormat::JsonGraph;
+    for arg in args {
+        match arg.as_str() {
+            option if option.starts_with("--format=") => {
+                format = option["--format=".len()..]
+                    .parse()
+                    .map_err(|_| CrawlError::new(ErrorType::InvalidOption))?
+            }
+            candidate if url.is_none() && !candidate.starts_with("--") => url = Some(candidate),
+            _ => return Err(CrawlError::new(ErrorType::InvalidOption)),
+        }
+    }
+
+    let domain = Domain::new(url.ok_or_else(|| CrawlError::new(ErrorType::MissingParameter))?)?;
+    let name = domain.get_name();
+    let pages = storage.get_pages(&name).unwrap_or_default();
+    let graph = storage
+        .get_graph(&name)
+        .ok_or_else(|| CrawlError::new(ErrorType::DomainNotCrawled))?;
+    print!("{}", export(&GraphJson::new(&name, graph), &pages, format));
+    Ok(EXIT_OK)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::json::{FetchError, FetchJson};
+    use crate::memory::MemoryStorage;
+    use crate::storage::bare_page;
+    use std::collections::HashSet;
+    use std::sync::Arc;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| String::from(*arg)).collect()
+    }
+
+    #[test]
+    fn test_crawl_args() {
+        let args = args(&[
+            "https://docs.rs",
+            "--include=regex:^/a+b$",
+            "--ignored-params=utm&ref%",
+            "--fail-on-broken",
+        ]);
+        let (url, fail_on_broken, query) = parse_crawl_args(&args).unwrap();
+        assert_eq!(url, "https://docs.rs");
+        assert!(fail_on_broken);
+        let pairs: Vec<(String, String)> = form_urlencoded::parse(query.as_bytes())
+            .into_owned()
+            .collect();
+        assert_eq!(
+            pairs,
+            vec![
+                (String::from("force_refresh"), String::from("true")),
+                (String::from("include"), String::from("regex:^/a+b$")),
+                (String::from("ignored_params"), String::from("utm&ref%")),
+            ]
+        );
+
+        assert!(parse_crawl_args(&args[1..]).is_err());
+        assert!(
+            parse_crawl_args(&["https://docs.rs".to_owned(), "--max-depth".to_owned()]).is_err()
+        );
+
+        let stored = [
+            "https://docs.rs".to_owned(),
+            "--force-refresh=false".to_owned(),
+        ];
+        let (_, fail_on_broken, query) = parse_crawl_args(&stored).unwrap();
+        assert!(!fail_on_broken);
+        assert!(query.ends_with("force_refresh=false"));
+    }
+
+    #[test]
+    fn test_exit_codes() {
+        let storage: SharedStorage = Arc::new(MemoryStorage::new());
+        assert_eq!(run(&args(&[]), &storage), EXIT_ERROR);
+        assert_eq!(run(&args(&["crawl"]), &storage), EXIT_ERROR);
+        assert_eq!(
+            run(&args(&["export", "https://docs.rs"]), &storage),
+            EXIT_ERROR
+        );
+
+        let ok = bare_page(String::from("https://docs.rs/"));
+        let mut broken = bare_page(String::from("https://docs.rs/missing"));
+        broken.fetch = Some(FetchJson {
+            status: Some(404),
+            error: Some(FetchError::Status),
+            content_type: None,
+            response_time_ms: 10,
+            size: 0,
+            etag: None,
+            last_modified: None,
+            content_hash: None,
+        });
+        let clean = UrlsJson::new("docs.rs", vec![ok.clone()], HashSet::new());
+        assert_eq!(report_broken_links(&clean, true), EXIT_OK);
+        let json = UrlsJson::new("docs.rs", vec![ok, broken], HashSet::new());
+        assert_eq!(report_broken_links(&json, false), EXIT_OK);
+        assert_eq!(report_broken_links(&json, true), EXIT_BROKEN_LINKS);
+    }
+}
@@ -1,29 +1,108 @@
-use crate::database::DataBaseConnection;
-use crate::domain::Domain;
+use crate::canonical::canonicalize;
+use crate::domain::{host_key, Domain};
 use crate::error::{CrawlError, ErrorType};
-use crate::json::UrlsJson;
-use crate::parsing::parse_html_links;
+use crate::json::{
+    DeltaJson, EdgeJson, FetchError, FetchJson, PageChange, PageJson, ProgressJson, SitemapJson,
+    StatsJson, UrlsJson,
+};
+use crate::metrics::page_metrics;
+use crate::options::CrawlOptions;
+use crate::parsing::{parse_html_links, Link, LinkSource};
+use crate::politeness::{Outcome, Scheduler};
+use crate::robots::Robots;
+use crate::sitemap::{read_sitemaps, sitemap_report, sitemap_urls, Sitemap};
+use crate::storage::{now_secs, SharedStorage};
 use crate::Result;
 
 use rayon::prelude::*;
 
-use reqwest::Url;
+use reqwest::header::{
+    HeaderMap, HeaderValue, CONTENT_TYPE, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED,
+    USER_AGENT,
+};
+use reqwest::{Client, Response, StatusCode, Url};
 
+use serde::{Deserialize, Serialize};
 use serde_json;
 
-use std::collections::HashSet;
-use std::env;
+use sha2::{Digest, Sha256};
+
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::io::Read;
 use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+// Number of times a url is requested again after the host asked the crawler to slow down.
+const MAX_RETRIES: usize = 2;
+
+// Number of bytes of an html page read at most, the links after them are not found.
+const MAX_PAGE_SIZE: u64 = 10 * 1024 * 1024;
+
+// Result of the request of a page: its outcome, the url it was redirected to, its html
+// content if it was successfully fetched, and whether the host answered that it did not
+// change since it was last fetched.
+struct FetchedPage {
+    fetch: FetchJson,
+    url: Url,
+    html: Option<String>,
+    not_modified: bool,
+}
+
+// A url waiting to be explored, with its depth and the kind of element and the page it was
+// found in. The urls the crawl starts from have neither, and the depth of the urls only
+// reached through the sitemaps is unknown.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct QueuedUrl {
+    pub url: String,
+    pub depth: Option<usize>,
+    #[serde(default)]
+    pub source: Option<LinkSource>,
+    #[serde(default)]
+    pub referrer: Option<String>,
+}
 
-// Given a Domain object, tries to crawl its pages starting with the originally requested url.
-pub fn crawl(domain: &Domain) -> Result<UrlsJson> {
-    let mut db = DataBaseConnection::new()?;
-    let limit = env::var("URL_LIST_MAX_SIZE")?.parse().unwrap_or(50);
+// State of a running crawl, saved after each level of the exploration: the depth of the
+// next level, the urls left to explore, the visited and blocked urls, and the links found
+// in each visited page.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Checkpoint {
+    pub depth: usize,
+    pub frontier: Vec<QueuedUrl>,
+    pub visited: HashMap<String, PageJson>,
+    pub blocked: HashSet<String>,
+    pub graph: HashMap<String, Vec<EdgeJson>>,
+}
+
+// Given a Domain object, tries to crawl its pages starting with the originally requested url
+// and the urls listed in its sitemaps. In sitemap-only mode, only lists the urls of the
+// sitemaps. The progress of the crawl is reported after each level of the exploration, and
+// the crawl of a job is saved at the same time so that the job can resume it.
+pub fn crawl(
+    storage: &SharedStorage,
+    domain: &Domain,
+    options: &CrawlOptions,
+    job_id: Option<&str>,
+    on_progress: &mut dyn FnMut(&ProgressJson),
+) -> Result<UrlsJson> {
+    let started = Instant::now();
+    let limit = options.limit;
+    let user_agent = &options.user_agent;
     let name = domain.get_name();
+    let rules = options.rules.compile()?;
 
-    // If the cache contains a set of urls for this domain name, returns that set.
-    if let Some(url_set) = db.get_set(&name) {
-        let json = UrlsJson::new(&name, url_set);
+    // If the cache contains a set of urls for this domain name that is younger than the TTL,
+    // returns that set. In stale-while-revalidate mode, an older set is returned as well, marked
+    // as stale so that the caller can crawl the domain again in the background.
+    let crawled_at = storage.get_crawled_at(&name);
+    let fresh = is_fresh(options.ttl_secs, crawled_at, now_secs());
+    if let Some(pages) = storage.get_pages(&name).filter(|_| {
+        !options.sitemap_only && !options.force_refresh && (fresh || options.stale_while_revalidate)
+    }) {
+        let mut json = UrlsJson::new(&name, pages, storage.get_blocked(&name));
+        json.rules = storage.get_rules(&name);
+        json.crawled_at = crawled_at;
+        json.stale = !fresh;
         println!(
             "url set extracted: {}",
             serde_json::to_string(&json).unwrap()
@@ -31,53 +110,778 @@ pub fn crawl(domain: &Domain) -> Result<UrlsJson> {
         return Ok(json);
     }
 
-    // Initializes a url queue and a shared set for visited urls
+    let scope = domain.scope(&options.scope)?;
+
+    // The requested url is crawled even if it matches none of the include patterns, as the
+    // pages they match are found from it, but not if it matches an exclude pattern.
     let url = Url::parse(domain.get_original_url())?;
-    let mut url_queue = Vec::new();
-    let url_set_pointer = Arc::new(Mutex::new(HashSet::new()));
-    url_queue.push(url);
+    if !options.sitemap_only && rules.excludes(&canonicalize(&url, &options.canonical)) {
+        return Err(CrawlError::new(ErrorType::InvalidRequest(String::from(
+            "The requested url matches one of the exclude patterns",
+        ))));
+    }
+
+    // Reads the robots.txt rules of each host of the crawl when one of its urls is met, from
+    // the cache if possible.
+    let client = create_client(user_agent)?;
+    let scheduler = Scheduler::new(options.politeness.clone());
+    let robots = HostRobots::new(storage, &client, &scheduler, user_agent);
+
+    // Reads the sitemaps of the domain, and keeps the urls of the domain they list along
+    // with their metadata.
+    let sitemap = if options.sitemaps || options.sitemap_only {
+        let listed_sitemaps = robots.with(&url, |robots| sitemap_urls(&url, robots.sitemaps()));
+        read_sitemaps(&client, &scheduler, listed_sitemaps)
+    } else {
+        Sitemap::default()
+    };
+    let listed: HashMap<String, SitemapJson> = sitemap
+        .pages
+        .into_iter()
+        .filter_map(|(loc, info)| {
+            Url::parse(&loc)
+                .ok()
+                .map(|loc| (canonicalize(&loc, &options.canonical), info))
+        })
+        .filter(|(loc, _)| scope.contains(loc) && rules.allows(loc))
+        .map(|(loc, info)| (loc.into_string(), info))
+        .collect();
+
+    // Lists the urls of the sitemaps that robots.txt allows without visiting them.
+    if options.sitemap_only {
+        let (allowed, blocked): (Vec<_>, Vec<_>) = listed.iter().partition(|(loc, _)| {
+            Url::parse(loc)
+                .map(|loc| robots.is_allowed(&loc))
+                .unwrap_or(false)
+        });
+        let pages = allowed
+            .into_iter()
+            .map(|(loc, info)| PageJson {
+                url: loc.clone(),
+                depth: None,
+                sources: vec![],
+                referrers: vec![],
+                sitemap: Some(info.clone()),
+                fetch: None,
+                metrics: None,
+                change: None,
+            })
+            .collect();
+        let blocked_set = blocked.into_iter().map(|(loc, _)| loc.clone()).collect();
+        let mut json = UrlsJson::new(&name, pages, blocked_set);
+        json.sitemap = Some(sitemap_report(sitemap.sitemaps, &listed, &json.urls));
+        json.rules = Some(options.rules.clone());
+        return Ok(json);
+    }
+
+    // Keeps the pages and the links of the previous crawl of the domain, to send conditional
+    // requests and to tell what changed since then.
+    let previous: HashMap<String, PageJson> = storage
+        .get_pages(&name)
+        .unwrap_or_default()
+        .into_iter()
+        .map(|page| (page.url.clone(), page))
+        .collect();
+    let previous_graph = storage.get_graph(&name).unwrap_or_default();
+    // The links stored for a page only stand for its links if the previous crawl followed
+    // them, and found them the same way.
+    let previous_options = storage.get_options(&name);
+    let links_kept = |page: &PageJson| {
+        previous_options
+            .as_ref()
+            .filter(|before| {
+                before.link_sources == options.link_sources
+                    && before.canonical == options.canonical
+                    && expands(page.depth, before.max_depth)
+            })
+            .is_some()
+    };
+
+    // Initializes a url queue with the depth of each url, a shared map of visited urls with
+    // their information, a shared set of blocked urls and a shared map of the links found in
+    // each page. The crawl starts from the requested url alone. If the crawl of the job was
+    // interrupted, starts again from its last checkpoint.
+    let checkpoint = job_id.and_then(|job_id| storage.get_checkpoint(job_id));
+    let (mut url_queue, visited, blocked, graph, mut level) = match checkpoint {
+        Some(checkpoint) => {
+            println!(
+                "Resuming the crawl of {} at depth {}",
+                name, checkpoint.depth
+            );
+            (
+                checkpoint.frontier,
+                checkpoint.visited,
+                checkpoint.blocked,
+                checkpoint.graph,
+                checkpoint.depth,
+            )
+        }
+        None => {
+            let seed = QueuedUrl {
+                url: url.into_string(),
+                depth: Some(0),
+                source: None,
+                referrer: None,
+            };
+            (
+                vec![seed],
+                HashMap::new(),
+                HashSet::new(),
+                HashMap::new(),
+                0,
+            )
+        }
+    };
+    let mut sitemap_queued = false;
+    let url_set_pointer = Arc::new(Mutex::new(visited));
+    let blocked_set_pointer = Arc::new(Mutex::new(blocked));
+    let graph_pointer = Arc::new(Mutex::new(graph));
 
     // Parallel threads are responsible to fetch the content of each url, mark it
     // as visited, parse its links and return them for another round of crawling.
-    while !url_queue.is_empty() {
+    // Each round goes one click deeper, until the maximum depth is reached. Once the links
+    // are all followed, the urls of the sitemaps that were not reached are explored as well.
+    loop {
+        if url_queue.is_empty() {
+            if sitemap_queued {
+                break;
+            }
+            sitemap_queued = true;
+            let visited = url_set_pointer.lock().unwrap();
+            url_queue = listed
+                .keys()
+                .filter(|loc| !visited.contains_key(*loc))
+                .map(|loc| QueuedUrl {
+                    url: loc.clone(),
+                    depth: None,
+                    source: None,
+                    referrer: None,
+                })
+                .collect();
+            continue;
+        }
         url_queue = url_queue
             .into_par_iter()
-            .map_with(url_set_pointer.clone(), |set, url| {
-                if set.lock().unwrap().len() >= limit {
-                    vec![]
-                } else if !domain.is_in_domain(&url) {
-                    println!("Outside the domain: {}", url);
-                    vec![]
-                } else if set.lock().unwrap().contains(url.as_str()) {
-                    println!("Already in domain: {}", url);
-                    vec![]
-                } else {
-                    println!("Adding: {}", url);
-                    set.lock().unwrap().insert(url.as_str().to_owned());
-                    match fetch(url) {
-                        Ok(html) => parse_html_links(domain, html),
-                        Err(_) => vec![],
+            .map_with(
+                (
+                    url_set_pointer.clone(),
+                    blocked_set_pointer.clone(),
+                    graph_pointer.clone(),
+                ),
+                |(set, blocked, graph), queued: QueuedUrl| {
+                    let url = match Url::parse(&queued.url) {
+                        Ok(url) => canonicalize(&url, &options.canonical),
+                        Err(_) => return vec![],
+                    };
+                    let depth = queued.depth;
+                    if set.lock().unwrap().len() >= limit {
+                        vec![]
+                    } else if !scope.contains(&url) {
+                        println!("Outside the domain: {}", url);
+                        vec![]
+                    } else if add_link(set, &url, &queued) {
+                        println!("Already in domain: {}", url);
+                        vec![]
+                    } else if !robots.is_allowed(&url) {
+                        println!("Blocked by robots.txt: {}", url);
+                        blocked.lock().unwrap().insert(url.as_str().to_owned());
+                        vec![]
+                    } else {
+                        println!("Adding: {}", url);
+                        let mut page = PageJson {
+                            url: url.as_str().to_owned(),
+                            depth,
+                            sources: vec![],
+                            referrers: vec![],
+                            sitemap: listed.get(url.as_str()).cloned(),
+                            fetch: None,
+                            metrics: None,
+                            change: None,
+                        };
+                        page.add_link(queued.source, queued.referrer);
+                        set.lock().unwrap().insert(page.url.clone(), page);
+                        let before = previous.get(url.as_str());
+                        // A page that is not modified keeps its previous links, so it is
+                        // only asked for conditionally when those links can be used.
+                        let validators = before
+                            .filter(|before| {
+                                !expands(depth, options.max_depth) || links_kept(before)
+                            })
+                            .and_then(|page| page.fetch.as_ref());
+                        let page = fetch(&client, &scheduler, url.clone(), validators);
+                        let not_modified = page.not_modified;
+                        if let Some(visited) = set.lock().unwrap().get_mut(url.as_str()) {
+                            if !previous.is_empty() {
+                                visited.change = Some(page_change(before, &page));
+                            }
+                            visited.fetch = Some(page.fetch);
+                        }
+
+                        // The links of the pages that did not change are the ones found by
+                        // the previous crawl.
+                        let links = match page.html {
+                            _ if !expands(depth, options.max_depth) => vec![],
+                            Some(html) => parse_html_links(
+                                &page.url,
+                                html,
+                                &options.link_sources,
+                                &options.canonical,
+                            ),
+                            None if not_modified => previous_graph
+                                .get(url.as_str())
+                                .map(|edges| stored_links(edges))
+                                .unwrap_or_default(),
+                            None => vec![],
+                        };
+                        let edges = links.iter().map(|link| create_edge(&url, link)).collect();
+                        graph.lock().unwrap().insert(url.as_str().to_owned(), edges);
+                        links
+                            .into_iter()
+                            .filter(|link| rules.allows(&link.url))
+                            .map(|link| QueuedUrl {
+                                url: link.url.into_string(),
+                                depth: depth.map(|depth| depth + 1),
+                                source: Some(link.source),
+                                referrer: Some(url.as_str().to_owned()),
+                            })
+                            .collect()
                     }
-                }
-            })
+                },
+            )
             .flatten()
             .collect();
+        level += 1;
+
+        // Saves the state of the crawl so that it can be resumed from the next level.
+        let checkpoint = Checkpoint {
+            depth: level,
+            frontier: url_queue.clone(),
+            visited: url_set_pointer.lock().unwrap().clone(),
+            blocked: blocked_set_pointer.lock().unwrap().clone(),
+            graph: graph_pointer.lock().unwrap().clone(),
+        };
+        if let Some(job_id) = job_id {
+            storage.set_checkpoint(job_id, &checkpoint)?;
+        }
+
+        on_progress(&ProgressJson {
+            depth: level - 1,
+            visited_urls: checkpoint.visited.len(),
+            blocked_urls: checkpoint.blocked.len(),
+            queued_urls: url_queue.len(),
+        });
     }
 
-    // Takes the set out its shared structure.
+    // Takes the sets out their shared structures.
     let url_set = Arc::try_unwrap(url_set_pointer)
         .map_err(|_| CrawlError::new(ErrorType::ScrapError))?
         .into_inner()
         .map_err(|_| CrawlError::new(ErrorType::ScrapError))?;
+    let blocked_set = Arc::try_unwrap(blocked_set_pointer)
+        .map_err(|_| CrawlError::new(ErrorType::ScrapError))?
+        .into_inner()
+        .map_err(|_| CrawlError::new(ErrorType::ScrapError))?;
+
+    let graph = Arc::try_unwrap(graph_pointer)
+        .map_err(|_| CrawlError::new(ErrorType::ScrapError))?
+        .into_inner()
+        .map_err(|_| CrawlError::new(ErrorType::ScrapError))?;
 
-    db.set(&name, url_set.clone())?;
-    let json = UrlsJson::new(&name, url_set);
+    // Computes the link metrics of each page before saving it.
+    let mut pages: Vec<PageJson> = url_set.into_values().collect();
+    let mut metrics = page_metrics(&pages, &graph);
+    for page in &mut pages {
+        page.metrics = metrics.remove(&page.url);
+    }
+    storage.set_pages(&name, &pages)?;
+    storage.set_blocked(&name, blocked_set.clone())?;
+    storage.set_graph(&name, &graph)?;
+    storage.set_rules(&name, &options.rules)?;
+    storage.set_options(&name, options)?;
+    // The time of the crawl names its snapshot, so it comes after the last snapshot even when
+    // two crawls end in the same second.
+    let crawled_at = storage
+        .get_snapshots(&name)
+        .last()
+        .map_or(0, |last| last + 1)
+        .max(now_secs());
+    storage.set_crawled_at(&name, crawled_at)?;
+    storage.add_snapshot(&name, crawled_at, &pages)?;
+    storage.unlock_refresh(&name)?;
+    if let Some(job_id) = job_id {
+        storage.delete_checkpoint(job_id)?;
+    }
+    let mut json = UrlsJson::new(&name, pages, blocked_set);
+    json.stats = Some(StatsJson {
+        duration_ms: started.elapsed().as_millis() as u64,
+        politeness: scheduler.stats(),
+    });
+    json.rules = Some(options.rules.clone());
+    json.crawled_at = Some(crawled_at);
+    if !previous.is_empty() {
+        json.delta = Some(DeltaJson::new(&json.urls, &previous));
+    }
+    if options.sitemaps {
+        json.sitemap = Some(sitemap_report(sitemap.sitemaps, &listed, &json.urls));
+    }
     Ok(json)
 }
 
-// Gets the html content of a page.
-fn fetch(link: Url) -> Result<String> {
-    reqwest::get(link)
-        .and_then(|mut resp| resp.text())
+// Checks that a crawl is younger than the TTL. Without a TTL, crawls never get stale, and
+// the crawls whose time was not recorded are stale as soon as there is one.
+fn is_fresh(ttl_secs: Option<u64>, crawled_at: Option<u64>, now: u64) -> bool {
+    match (ttl_secs, crawled_at) {
+        (None, _) => true,
+        (Some(ttl), Some(crawled_at)) => now < crawled_at.saturating_add(ttl),
+        (Some(_), None) => false,
+    }
+}
+
+// Checks if a url was already visited, and records the kind of element and the page it was
+// found in this time.
+fn add_link(set: &Mutex<HashMap<String, PageJson>>, url: &Url, queued: &QueuedUrl) -> bool {
+    match set.lock().unwrap().get_mut(url.as_str()) {
+        Some(page) => {
+            page.add_link(queued.source, queued.referrer.clone());
+            true
+        }
+        None => false,
+    }
+}
+
+// Compares the outcome of the request of a url with its outcome in the previous crawl. A
+// 304 response or a body with the same hash means that the page did not change.
+fn page_change(before: Option<&PageJson>, page: &FetchedPage) -> PageChange {
+    let before = match before.and_then(|page| page.fetch.as_ref()) {
+        Some(before) => before,
+        None => return PageChange::New,
+    };
+    let fetch = &page.fetch;
+    let same_content = match (&fetch.content_hash, &before.content_hash) {
+        (Some(hash), Some(before_hash)) => hash == before_hash,
+        // The bodies that were not read are compared with their validators and their size.
+        (None, None) => {
+            (fetch.etag.is_some() || fetch.last_modified.is_some())
+                && fetch.etag == before.etag
+                && fetch.last_modified == before.last_modified
+                && fetch.size == before.size
+        }
+        _ => false,
+    };
+    if page.not_modified || (same_content && fetch.status == before.status) {
+        PageChange::Unchanged
+    } else {
+        PageChange::Changed
+    }
+}
+
+// Tells whether the links of a page at that depth are followed with that maximum depth.
+// Without a known depth, a page cannot be kept within the maximum depth, so its links are only
+// followed when there is none.
+fn expands(depth: Option<usize>, max_depth: Option<usize>) -> bool {
+    match max_depth {
+        Some(max) => depth.filter(|depth| *depth < max).is_some(),
+        None => true,
+    }
+}
+
+// Reads the links of a page from the edges of the link graph going from it.
+fn stored_links(edges: &[EdgeJson]) -> Vec<Link> {
+    edges
+        .iter()
+        .filter_map(|edge| {
+            Url::parse(&edge.target).ok().map(|url| Link {
+                url,
+                source: edge.element,
+                text: edge.text.clone(),
+                rel: edge.rel.clone(),
+            })
+        })
+        .collect()
+}
+
+// Creates the edge of the link graph going from a page to one of its links.
+fn create_edge(page_url: &Url, link: &Link) -> EdgeJson {
+    EdgeJson {
+        source: page_url.as_str().to_owned(),
+        target: link.url.as_str().to_owned(),
+        text: link.text.clone(),
+        rel: link.rel.clone(),
+        element: link.source,
+    }
+}
+
+// Creates an http client that identifies itself with the given user-agent.
+fn create_client(user_agent: &str) -> Result<Client> {
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        USER_AGENT,
+        HeaderValue::from_str(user_agent).map_err(|_| CrawlError::new(ErrorType::EnvError))?,
+    );
+    Client::builder()
+        .default_headers(headers)
+        .build()
         .map_err(|_| CrawlError::new(ErrorType::FetchError))
 }
+
+// Requests a page once the scheduler allows it, and tries again later if the host answers
+// that it is overloaded. If the page was fetched before, only asks for it if it changed since
+// then, and keeps what was known about it when it did not.
+fn fetch(
+    client: &Client,
+    scheduler: &Scheduler,
+    link: Url,
+    before: Option<&FetchJson>,
+) -> FetchedPage {
+    let before = before.filter(|before| before.error.is_none());
+    let mut attempts = 0;
+    loop {
+        let mut request = client.get(link.clone());
+        if let Some(etag) = before.and_then(|before| before.etag.as_ref()) {
+            request = request.header(IF_NONE_MATCH, etag.as_str());
+        }
+        if let Some(date) = before.and_then(|before| before.last_modified.as_ref()) {
+            request = request.header(IF_MODIFIED_SINCE, date.as_str());
+        }
+        let permit = scheduler.acquire(&link);
+        let sent_at = Instant::now();
+        let (outcome, mut page) = match request.send() {
+            Ok(mut resp) => (
+                Outcome::from_response(resp.status(), resp.headers()),
+                read_page(&mut resp, sent_at),
+            ),
+            Err(e) => (
+                Outcome::Failed,
+                FetchedPage {
+                    fetch: FetchJson {
+                        status: None,
+                        error: Some(fetch_error(&e)),
+                        content_type: None,
+                        response_time_ms: sent_at.elapsed().as_millis() as u64,
+                        size: 0,
+                        etag: None,
+                        last_modified: None,
+                        content_hash: None,
+                    },
+                    url: link.clone(),
+                    html: None,
+                    not_modified: false,
+                },
+            ),
+        };
+        scheduler.release(permit, &outcome);
+
+        attempts += 1;
+        if !outcome.is_throttled() || attempts > MAX_RETRIES {
+            // The page keeps its previous status, and only its new validators.
+            if let (Some(304), Some(before)) = (page.fetch.status, before) {
+                page.not_modified = true;
+                let fetch = &mut page.fetch;
+                fetch.status = before.status;
+                fetch.content_type = before.content_type.clone();
+                fetch.size = before.size;
+                fetch.etag = fetch.etag.take().or_else(|| before.etag.clone());
+                fetch.last_modified = fetch
+                    .last_modified
+                    .take()
+                    .or_else(|| before.last_modified.clone());
+                fetch.content_hash = before.content_hash.clone();
+            }
+            return page;
+        }
+        println!("Throttled, retrying later: {}", link);
+    }
+}
+
+// Reads the body of a response, up to MAX_PAGE_SIZE bytes, if its content type is html or
+// unknown. Only keeps it as html content for the successful responses. The other bodies are
+// not downloaded, their size is the one announced by the response.
+fn read_page(resp: &mut Response, sent_at: Instant) -> FetchedPage {
+    let status = resp.status();
+    let header = |name| {
+        resp.headers()
+            .get(name)
+            .and_then(|value: &HeaderValue| value.to_str().ok())
+            .map(ToOwned::to_owned)
+    };
+    let content_type = header(CONTENT_TYPE);
+    let etag = header(ETAG);
+    let last_modified = header(LAST_MODIFIED);
+    let html_body = is_html(content_type.as_deref());
+    let mut body = vec![];
+    let read = if html_body {
+        resp.take(MAX_PAGE_SIZE)
+            .read_to_end(&mut body)
+            .map(|_| Some(format!("{:x}", Sha256::digest(&body))))
+    } else {
+        Ok(None)
+    };
+    let size = match read {
+        Ok(Some(_)) => body.len(),
+        _ => resp.content_length().unwrap_or_default() as usize,
+    };
+
+    let error = match read {
+        Err(_) => Some(FetchError::Body),
+        Ok(_) if status.is_client_error() || status.is_server_error() => Some(FetchError::Status),
+        Ok(_) => None,
+    };
+    let html = if error.is_none() && status.is_success() && html_body {
+        Some(String::from_utf8_lossy(&body).into_owned())
+    } else {
+        None
+    };
+    FetchedPage {
+        fetch: FetchJson {
+            status: Some(status.as_u16()),
+            error,
+            content_type,
+            response_time_ms: sent_at.elapsed().as_millis() as u64,
+            size,
+            etag,
+            last_modified,
+            content_hash: read.ok().and_then(|hash| hash),
+        },
+        url: resp.url().clone(),
+        html,
+        not_modified: false,
+    }
+}
+
+// Checks if a content type is html. Pages without content type are read as html too.
+fn is_html(content_type: Option<&str>) -> bool {
+    content_type
+        .map(|content_type| {
+            let mime = content_type.split(';').next().unwrap_or("").trim();
+            mime.eq_ignore_ascii_case("text/html")
+                || mime.eq_ignore_ascii_case("application/xhtml+xml")
+        })
+        .unwrap_or(true)
+}
+
+// Sorts the errors of the http client into the kinds of errors reported for each url.
+fn fetch_error(error: &reqwest::Error) -> FetchError {
+    if error.is_timeout() {
+        return FetchError::Timeout;
+    }
+    if error.is_redirect() {
+        return FetchError::Redirect;
+    }
+    match error
+        .get_ref()
+        .and_then(|inner| inner.downcast_ref::<hyper::Error>())
+    {
+        Some(inner) if inner.is_connect() => {
+            // The tls errors only show in the messages of the underlying errors.
+            let mut source: Option<&dyn Error> = Some(inner);
+            while let Some(cause) = source {
+                let message = cause.to_string().to_lowercase();
+                if ["tls", "ssl", "certificate", "handshake"]
+                    .iter()
+                    .any(|word| message.contains(word))
+                {
+                    return FetchError::Tls;
+                }
+                source = cause.source();
+            }
+            FetchError::Connect
+        }
+        _ => FetchError::Other,
+    }
+}
+
+// The robots.txt rules of the hosts of a crawl, read the first time a url of a host is met,
+// from the cache of the storage or from the host. The Crawl-delay of each host is given to
+// the scheduler of the crawl.
+struct HostRobots<'a> {
+    storage: &'a SharedStorage,
+    client: &'a Client,
+    scheduler: &'a Scheduler,
+    user_agent: &'a str,
+    hosts: Mutex<HashMap<String, Robots>>,
+}
+
+impl<'a> HostRobots<'a> {
+    fn new(
+        storage: &'a SharedStorage,
+        client: &'a Client,
+        scheduler: &'a Scheduler,
+        user_agent: &'a str,
+    ) -> Self {
+        HostRobots {
+            storage,
+            client,
+            scheduler,
+            user_agent,
+            hosts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Checks if the rules of the host of the url allow the crawler to visit it.
+    fn is_allowed(&self, url: &Url) -> bool {
+        self.with(url, |robots| robots.is_allowed(url))
+    }
+
+    // Calls a function with the rules of the host of the url. The lock is not held while
+    // the rules of a new host are read, so that the other hosts are not held up.
+    fn with<T>(&self, url: &Url, f: impl FnOnce(&Robots) -> T) -> T {
+        let host = host_key(url).unwrap_or_default();
+        if let Some(robots) = self.hosts.lock().unwrap().get(&host) {
+            return f(robots);
+        }
+        let robots = match self.storage.get_robots(&host, self.user_agent) {
+            Some(robots) => robots,
+            None => {
+                let robots = fetch_robots(self.client, url, self.user_agent);
+                if let Err(e) = self.storage.set_robots(&host, &robots) {
+                    println!("robots.txt of {} not cached: {}", host, e.kind.message());
+                }
+                robots
+            }
+        };
+        self.scheduler.set_crawl_delay(url, robots.crawl_delay());
+        f(self.hosts.lock().unwrap().entry(host).or_insert(robots))
+    }
+}
+
+// Gets the robots.txt file at the root of the url's host. A missing or unreachable
+// file means that the whole domain can be crawled.
+fn fetch_robots(client: &Client, url: &Url, user_agent: &str) -> Robots {
+    let robots_url = match url.join("/robots.txt") {
+        Ok(robots_url) => robots_url,
+        Err(_) => return Robots::allow_all(user_agent),
+    };
+    match client.get(robots_url).send() {
+        Ok(ref mut resp) if resp.status() == StatusCode::OK => resp
+            .text()
+            .map(|content| Robots::parse(&content, user_agent))
+            .unwrap_or_else(|_| Robots::allow_all(user_agent)),
+        _ => Robots::allow_all(user_agent),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::MemoryStorage;
+    use crate::politeness::PolitenessConfig;
+
+    #[test]
+    fn test_html_content_type() {
+        assert!(is_html(Some("text/html; charset=utf-8")));
+        assert!(is_html(Some("Application/XHTML+XML")));
+        assert!(is_html(None));
+        assert!(!is_html(Some("application/pdf")));
+        assert!(!is_html(Some("text/plain")));
+    }
+
+    #[test]
+    fn test_crawl_freshness() {
+        assert!(is_fresh(None, None, 1000));
+        assert!(is_fresh(None, Some(0), 1000));
+        assert!(is_fresh(Some(60), Some(950), 1000));
+        assert!(!is_fresh(Some(60), Some(940), 1000));
+        assert!(!is_fresh(Some(60), None, 1000));
+        assert!(is_fresh(Some(u64::MAX), Some(10), 1000));
+    }
+
+    #[test]
+    fn test_host_robots() {
+        let storage: SharedStorage = Arc::new(MemoryStorage::new());
+        let user_agent = "rust-crawler";
+        let cached = [
+            ("example.com", "Disallow: /private\nCrawl-delay: 1"),
+            ("blog.example.com:8080", "Disallow: /drafts\nCrawl-delay: 2"),
+        ];
+        for (host, rules) in &cached {
+            let content = format!("User-agent: *\n{}", rules);
+            storage
+                .set_robots(host, &Robots::parse(&content, user_agent))
+                .unwrap();
+        }
+        let client = create_client(user_agent).unwrap();
+        let scheduler = Scheduler::new(PolitenessConfig::default());
+        let robots = HostRobots::new(&storage, &client, &scheduler, user_agent);
+
+        // Each host is checked against its own rules, read from the cache.
+        let url = |url: &str| Url::parse(url).unwrap();
+        assert!(!robots.is_allowed(&url("https://example.com/private/a")));
+        assert!(robots.is_allowed(&url("https://example.com/drafts/a")));
+        assert!(!robots.is_allowed(&url("https://blog.example.com:8080/drafts/a")));
+        assert!(robots.is_allowed(&url("https://blog.example.com:8080/private/a")));
+
+        let delays = scheduler.stats().current_delays_ms;
+        assert_eq!(delays["example.com"], 1000);
+        assert_eq!(delays["blog.example.com"], 2000);
+    }
+
+    #[test]
+    fn test_link_expansion() {
+        assert!(expands(Some(5), None));
+        assert!(expands(None, None));
+        assert!(expands(Some(1), Some(2)));
+        assert!(!expands(Some(2), Some(2)));
+        assert!(!expands(None, Some(2)));
+    }
+
+    #[test]
+    fn test_page_change() {
+        let fetch = |status, hash: &str| FetchJson {
+            status: Some(status),
+            error: None,
+            content_type: None,
+            response_time_ms: 10,
+            size: 100,
+            etag: None,
+            last_modified: None,
+            content_hash: Some(hash.to_owned()),
+        };
+        let page = |status, hash: &str| FetchedPage {
+            fetch: fetch(status, hash),
+            url: Url::parse("https://docs.rs/").unwrap(),
+            html: None,
+            not_modified: status == 304,
+        };
+        let before = PageJson {
+            url: String::from("https://docs.rs/"),
+            depth: Some(0),
+            sources: vec![],
+            referrers: vec![],
+            sitemap: None,
+            fetch: Some(fetch(200, "a1")),
+            metrics: None,
+            change: None,
+        };
+
+        assert_eq!(page_change(None, &page(200, "a1")), PageChange::New);
+        assert_eq!(
+            page_change(Some(&before), &page(304, "a1")),
+            PageChange::Unchanged
+        );
+        assert_eq!(
+            page_change(Some(&before), &page(200, "a1")),
+            PageChange::Unchanged
+        );
+        assert_eq!(
+            page_change(Some(&before), &page(200, "b2")),
+            PageChange::Changed
+        );
+        assert_eq!(
+            page_change(Some(&before), &page(404, "a1")),
+            PageChange::Changed
+        );
+
+        // The bodies that are not html are not hashed.
+        let mut unread = page(200, "");
+        unread.fetch.content_hash = None;
+        unread.fetch.etag = Some(String::from("\"v1\""));
+        let mut before = before;
+        before.fetch = Some(unread.fetch.clone());
+        assert_eq!(page_change(Some(&before), &unread), PageChange::Unchanged);
+        unread.fetch.size = 200;
+        assert_eq!(page_change(Some(&before), &unread), PageChange::Changed);
+        unread.fetch.size = 100;
+        unread.fetch.etag = None;
+        assert_eq!(page_change(Some(&before), &unread), PageChange::Changed);
+    }
+}
@@ -1,53 +1,598 @@
+use crate::crawler::Checkpoint;
+use crate::json::{EdgeJson, JobJson, JobState, PageJson, SnapshotPageJson};
+use crate::keys::{self, Keys};
+use crate::options::CrawlOptions;
+use crate::robots::Robots;
+use crate::rules::UrlRules;
+use crate::storage::{
+    bare_page, snapshots_kept, to_json, Storage, JOB_TTL, REFRESH_LOCK_TTL, ROBOTS_TTL,
+};
 use crate::Result;
-use redis::{Client, Commands, Connection};
+use r2d2::{ManageConnection, Pool, PooledConnection};
+use redis::{Client, Commands, Connection, PipelineCommands, RedisError};
 
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::env;
+use std::time::Duration;
 
-// Wraps around a database connection and provides the api to read/write sets of urls
-// and read their length.
-pub struct DataBaseConnection {
-    connection: Connection,
+// Number of connections of the pool when REDIS_POOL_SIZE is not set.
+const POOL_SIZE: u32 = 16;
+
+// Number of seconds to wait for a connection of the pool before giving up.
+const POOL_TIMEOUT: u64 = 5;
+
+// Number of seconds an unused connection stays open.
+const IDLE_TIMEOUT: u64 = 5 * 60;
+
+// Parts of a checkpoint as they are stored: the depth, the frontier, the visited urls, the
+// blocked urls and the graph, serialized.
+type StoredCheckpoint = (
+    Option<usize>,
+    Vec<String>,
+    HashMap<String, String>,
+    HashSet<String>,
+    HashMap<String, String>,
+);
+
+// Opens the connections of the pool, checks them with a PING when they are taken from the
+// pool, and replaces the ones that were closed, so that the storage reconnects after the
+// database restarts.
+pub struct RedisManager {
+    client: Client,
 }
 
-impl DataBaseConnection {
-    // Creates a new connection with the database_url environment variable.
+impl ManageConnection for RedisManager {
+    type Connection = Connection;
+    type Error = RedisError;
+
+    fn connect(&self) -> std::result::Result<Connection, RedisError> {
+        self.client.get_connection()
+    }
+
+    fn is_valid(&self, connection: &mut Connection) -> std::result::Result<(), RedisError> {
+        redis::cmd("PING").query(connection)
+    }
+
+    fn has_broken(&self, connection: &mut Connection) -> bool {
+        !connection.is_open()
+    }
+}
+
+// Redis storage backend, the keys are laid out as described in the keys module. The
+// connections are shared between the threads through a pool.
+pub struct RedisStorage {
+    pool: Pool<RedisManager>,
+    keys: Keys,
+}
+
+impl RedisStorage {
+    // Creates the pool of connections with the DATABASE_URL and REDIS_POOL_SIZE environment
+    // variables, and checks that the database can be reached. The keys start with
+    // REDIS_KEY_PREFIX.
     pub fn new() -> Result<Self> {
         let address =
             env::var("DATABASE_URL").unwrap_or_else(|_| String::from("redis://127.0.0.1/"));
-        Ok(DataBaseConnection {
-            connection: Client::open(address.as_str())?.get_connection()?,
+        let size = env::var("REDIS_POOL_SIZE")
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(POOL_SIZE)
+            .max(1);
+        let manager = RedisManager {
+            client: Client::open(address.as_str())?,
+        };
+        let pool = Pool::builder()
+            .max_size(size)
+            .min_idle(Some(1))
+            .connection_timeout(Duration::from_secs(POOL_TIMEOUT))
+            .idle_timeout(Some(Duration::from_secs(IDLE_TIMEOUT)))
+            .build(manager)?;
+        let prefix =
+            env::var("REDIS_KEY_PREFIX").unwrap_or_else(|_| String::from(keys::DEFAULT_PREFIX));
+        Ok(RedisStorage {
+            pool,
+            keys: Keys::new(&prefix),
         })
     }
 
-    // Returns a set of urls if it exists in the Redis database
-    pub fn get_set(&mut self, domain_name: &str) -> Option<HashSet<String>> {
-        match self.connection.exists::<&str, bool>(domain_name) {
-            Ok(exists) if exists => self.connection.smembers(domain_name).ok(),
-            _ => None,
+    // Takes a connection from the pool, it goes back to the pool when it is dropped.
+    fn connection(&self) -> Result<PooledConnection<RedisManager>> {
+        Ok(self.pool.get()?)
+    }
+
+    // Moves the set of urls of a domain and every key stored next to it.
+    fn migrate_domain(&self, connection: &Connection, domain_name: &str) -> Result<()> {
+        println!("migration of the domain with name: {}", domain_name);
+        let snapshots: Vec<u64> = connection
+            .zrange(format!("{}:snapshots", domain_name), 0, -1)
+            .unwrap_or_default();
+        for crawled_at in snapshots {
+            let key = format!("{}:snapshot:{}", domain_name, crawled_at);
+            rename(
+                connection,
+                &key,
+                &self.keys.snapshot(domain_name, crawled_at),
+            )?;
+        }
+        for part in keys::LEGACY_PARTS.iter() {
+            let key = format!("{}:{}", domain_name, part);
+            rename(connection, &key, &self.keys.domain(domain_name, part))?;
         }
+        let checkpoint: Vec<String> = keys::LEGACY_CHECKPOINT_PARTS
+            .iter()
+            .map(|part| format!("{}:{}", domain_name, part))
+            .collect();
+        connection.del::<_, ()>(checkpoint)?;
+        rename(
+            connection,
+            domain_name,
+            &self.keys.domain(domain_name, "urls"),
+        )?;
+        Ok(())
+    }
+}
+
+impl Storage for RedisStorage {
+    // Redis does not keep empty sets, so a set exists if it has urls.
+    fn get_set(&self, domain_name: &str) -> Option<HashSet<String>> {
+        let urls: HashSet<String> = self
+            .connection()
+            .ok()?
+            .smembers(self.keys.domain(domain_name, "urls"))
+            .ok()?;
+        Some(urls).filter(|urls| !urls.is_empty())
     }
 
-    // Returns the length of a set of urls if it exists in the Redis database
-    pub fn get_len<'a>(&mut self, domain_name: &'a str) -> Option<(&'a str, usize)> {
-        match self.connection.scard(domain_name) {
+    fn get_len<'a>(&self, domain_name: &'a str) -> Option<(&'a str, usize)> {
+        let connection = self.connection().ok()?;
+        match connection.scard(self.keys.domain(domain_name, "urls")) {
             Ok(size) if size > 0 => Some((domain_name, size)),
             _ => None,
         }
     }
 
-    // Inserts a set in the Redis database
-    pub fn set(&mut self, domain_name: &str, domain_set: HashSet<String>) -> Result<()> {
+    fn set(&self, domain_name: &str, domain_set: HashSet<String>) -> Result<()> {
         println!("insertion in the database with name: {}", domain_name);
-        Ok(self.connection.sadd(domain_name, domain_set)?)
+        let key = self.keys.domain(domain_name, "urls");
+        let mut pipe = redis::pipe();
+        pipe.atomic().del(&key).ignore();
+        if !domain_set.is_empty() {
+            pipe.sadd(&key, domain_set).ignore();
+        }
+        Ok(pipe.query(&*self.connection()?)?)
+    }
+
+    // The urls and the information about them are read in a single transaction.
+    fn get_pages(&self, domain_name: &str) -> Option<Vec<PageJson>> {
+        let (url_set, mut stored): (HashSet<String>, HashMap<String, String>) = redis::pipe()
+            .atomic()
+            .smembers(self.keys.domain(domain_name, "urls"))
+            .hgetall(self.keys.domain(domain_name, "pages"))
+            .query(&*self.connection().ok()?)
+            .ok()?;
+        if url_set.is_empty() {
+            return None;
+        }
+        Some(
+            url_set
+                .into_iter()
+                .map(|url| {
+                    stored
+                        .remove(&url)
+                        .and_then(|page| serde_json::from_str(&page).ok())
+                        .unwrap_or_else(|| bare_page(url))
+                })
+                .collect(),
+        )
+    }
+
+    fn set_pages(&self, domain_name: &str, pages: &[PageJson]) -> Result<()> {
+        if pages.is_empty() {
+            return Ok(());
+        }
+        let urls: Vec<&str> = pages.iter().map(|page| page.url.as_str()).collect();
+        let fields = pages
+            .iter()
+            .map(|page| to_json(page).map(|content| (page.url.clone(), content)))
+            .collect::<Result<Vec<(String, String)>>>()?;
+        let urls_key = self.keys.domain(domain_name, "urls");
+        let key = self.keys.domain(domain_name, "pages");
+        println!("insertion in the database with name: {}", domain_name);
+        Ok(redis::pipe()
+            .atomic()
+            .del(&urls_key)
+            .ignore()
+            .del(&key)
+            .ignore()
+            .sadd(&urls_key, urls)
+            .ignore()
+            .hset_multiple(&key, &fields)
+            .ignore()
+            .query(&*self.connection()?)?)
+    }
+
+    fn get_snapshots(&self, domain_name: &str) -> Vec<u64> {
+        self.connection()
+            .ok()
+            .and_then(|connection| {
+                connection
+                    .zrange(self.keys.domain(domain_name, "snapshots"), 0, -1)
+                    .ok()
+            })
+            .unwrap_or_default()
+    }
+
+    // The index of the snapshots and the snapshot are read in a single transaction.
+    fn get_snapshot(
+        &self,
+        domain_name: &str,
+        crawled_at: u64,
+    ) -> Option<HashMap<String, SnapshotPageJson>> {
+        let (indexed, stored): (Option<f64>, HashMap<String, String>) = redis::pipe()
+            .atomic()
+            .zscore(self.keys.domain(domain_name, "snapshots"), crawled_at)
+            .hgetall(self.keys.snapshot(domain_name, crawled_at))
+            .query(&*self.connection().ok()?)
+            .ok()?;
+        indexed?;
+        Some(
+            stored
+                .into_iter()
+                .filter_map(|(url, page)| serde_json::from_str(&page).ok().map(|p| (url, p)))
+                .collect(),
+        )
+    }
+
+    fn add_snapshot(&self, domain_name: &str, crawled_at: u64, pages: &[PageJson]) -> Result<()> {
+        let fields = pages
+            .iter()
+            .map(|page| {
+                to_json(&SnapshotPageJson::new(page)).map(|content| (page.url.clone(), content))
+            })
+            .collect::<Result<Vec<(String, String)>>>()?;
+        let connection = self.connection()?;
+        let index = self.keys.domain(domain_name, "snapshots");
+        let key = self.keys.snapshot(domain_name, crawled_at);
+        // Stores the snapshot and reads the outdated ones in the same transaction, they are
+        // removed in a second one only when there are some.
+        let mut pipe = redis::pipe();
+        pipe.atomic().del(&key).ignore();
+        if !fields.is_empty() {
+            pipe.hset_multiple(&key, &fields).ignore();
+        }
+        pipe.zadd(&index, crawled_at, crawled_at).ignore().zrange(
+            &index,
+            0,
+            -(snapshots_kept() as isize) - 1,
+        );
+        let (outdated,): (Vec<u64>,) = pipe.query(&*connection)?;
+        if outdated.is_empty() {
+            return Ok(());
+        }
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        for old in outdated {
+            pipe.del(self.keys.snapshot(domain_name, old))
+                .ignore()
+                .zrem(&index, old)
+                .ignore();
+        }
+        Ok(pipe.query(&*connection)?)
+    }
+
+    fn get_blocked(&self, domain_name: &str) -> HashSet<String> {
+        self.connection()
+            .ok()
+            .and_then(|connection| {
+                connection
+                    .smembers(self.keys.domain(domain_name, "blocked"))
+                    .ok()
+            })
+            .unwrap_or_default()
+    }
+
+    fn set_blocked(&self, domain_name: &str, blocked_set: HashSet<String>) -> Result<()> {
+        let key = self.keys.domain(domain_name, "blocked");
+        let mut pipe = redis::pipe();
+        pipe.atomic().del(&key).ignore();
+        if !blocked_set.is_empty() {
+            pipe.sadd(&key, blocked_set).ignore();
+        }
+        Ok(pipe.query(&*self.connection()?)?)
+    }
+
+    fn get_graph(&self, domain_name: &str) -> Option<HashMap<String, Vec<EdgeJson>>> {
+        let stored: HashMap<String, String> = self
+            .connection()
+            .ok()?
+            .hgetall(self.keys.domain(domain_name, "graph"))
+            .ok()?;
+        if stored.is_empty() {
+            return None;
+        }
+        Some(
+            stored
+                .into_iter()
+                .filter_map(|(url, edges)| serde_json::from_str(&edges).ok().map(|e| (url, e)))
+                .collect(),
+        )
+    }
+
+    fn set_graph(&self, domain_name: &str, graph: &HashMap<String, Vec<EdgeJson>>) -> Result<()> {
+        let fields = graph
+            .iter()
+            .map(|(url, edges)| to_json(edges).map(|edges| (url.clone(), edges)))
+            .collect::<Result<Vec<(String, String)>>>()?;
+        let key = self.keys.domain(domain_name, "graph");
+        let mut pipe = redis::pipe();
+        pipe.atomic().del(&key).ignore();
+        if !fields.is_empty() {
+            pipe.hset_multiple(&key, &fields).ignore();
+        }
+        Ok(pipe.query(&*self.connection()?)?)
     }
+
+    fn get_rules(&self, domain_name: &str) -> Option<UrlRules> {
+        self.connection()
+            .ok()?
+            .hget::<String, &str, Option<String>>(self.keys.domain(domain_name, "meta"), "rules")
+            .ok()
+            .and_then(|content| content)
+            .and_then(|content| serde_json::from_str(&content).ok())
+    }
+
+    fn set_rules(&self, domain_name: &str, rules: &UrlRules) -> Result<()> {
+        let content = to_json(rules)?;
+        Ok(self
+            .connection()?
+            .hset(self.keys.domain(domain_name, "meta"), "rules", content)?)
+    }
+
+    fn get_options(&self, domain_name: &str) -> Option<CrawlOptions> {
+        self.connection()
+            .ok()?
+            .hget::<String, &str, Option<String>>(self.keys.domain(domain_name, "meta"), "options")
+            .ok()
+            .and_then(|content| content)
+            .and_then(|content| serde_json::from_str(&content).ok())
+    }
+
+    fn set_options(&self, domain_name: &str, options: &CrawlOptions) -> Result<()> {
+        let content = to_json(options)?;
+        Ok(self
+            .connection()?
+            .hset(self.keys.domain(domain_name, "meta"), "options", content)?)
+    }
+
+    fn get_crawled_at(&self, domain_name: &str) -> Option<u64> {
+        self.connection()
+            .ok()?
+            .hget::<String, &str, Option<u64>>(self.keys.domain(domain_name, "meta"), "crawled_at")
+            .ok()
+            .and_then(|crawled_at| crawled_at)
+    }
+
+    fn set_crawled_at(&self, domain_name: &str, crawled_at: u64) -> Result<()> {
+        Ok(self.connection()?.hset(
+            self.keys.domain(domain_name, "meta"),
+            "crawled_at",
+            crawled_at,
+        )?)
+    }
+
+    fn lock_refresh(&self, domain_name: &str) -> Result<bool> {
+        let locked: Option<String> = redis::cmd("SET")
+            .arg(self.keys.domain(domain_name, "refreshing"))
+            .arg(1)
+            .arg("NX")
+            .arg("EX")
+            .arg(REFRESH_LOCK_TTL)
+            .query(&*self.connection()?)?;
+        Ok(locked.is_some())
+    }
+
+    fn unlock_refresh(&self, domain_name: &str) -> Result<()> {
+        Ok(self
+            .connection()?
+            .del(self.keys.domain(domain_name, "refreshing"))?)
+    }
+
+    fn get_robots(&self, domain_name: &str, user_agent: &str) -> Option<Robots> {
+        self.connection()
+            .ok()?
+            .get::<String, Option<String>>(self.keys.domain(domain_name, "robots"))
+            .ok()
+            .and_then(|content| content)
+            .and_then(|content| serde_json::from_str::<Robots>(&content).ok())
+            .filter(|robots| robots.user_agent == user_agent)
+    }
+
+    fn set_robots(&self, domain_name: &str, robots: &Robots) -> Result<()> {
+        let content = to_json(robots)?;
+        Ok(self.connection()?.set_ex(
+            self.keys.domain(domain_name, "robots"),
+            content,
+            ROBOTS_TTL,
+        )?)
+    }
+
+    // The checkpoint is read in a single transaction.
+    fn get_checkpoint(&self, job_id: &str) -> Option<Checkpoint> {
+        let (depth, frontier, visited, blocked, graph): StoredCheckpoint = redis::pipe()
+            .atomic()
+            .get(self.keys.checkpoint(job_id, "depth"))
+            .lrange(self.keys.checkpoint(job_id, "frontier"), 0, -1)
+            .hgetall(self.keys.checkpoint(job_id, "visited"))
+            .smembers(self.keys.checkpoint(job_id, "blocked"))
+            .hgetall(self.keys.checkpoint(job_id, "graph"))
+            .query(&*self.connection().ok()?)
+            .ok()?;
+        Some(Checkpoint {
+            depth: depth?,
+            frontier: frontier
+                .iter()
+                .filter_map(|item| serde_json::from_str(item).ok())
+                .collect(),
+            visited: visited
+                .into_iter()
+                .filter_map(|(url, page)| serde_json::from_str(&page).ok().map(|page| (url, page)))
+                .collect(),
+            blocked,
+            graph: graph
+                .into_iter()
+                .filter_map(|(url, edges)| serde_json::from_str(&edges).ok().map(|e| (url, e)))
+                .collect(),
+        })
+    }
+
+    // The checkpoint is replaced in a single transaction.
+    fn set_checkpoint(&self, job_id: &str, checkpoint: &Checkpoint) -> Result<()> {
+        let frontier = checkpoint
+            .frontier
+            .iter()
+            .map(to_json)
+            .collect::<Result<Vec<String>>>()?;
+        let visited = checkpoint
+            .visited
+            .iter()
+            .map(|(url, page)| to_json(page).map(|page| (url.clone(), page)))
+            .collect::<Result<Vec<(String, String)>>>()?;
+        let graph = checkpoint
+            .graph
+            .iter()
+            .map(|(url, edges)| to_json(edges).map(|edges| (url.clone(), edges)))
+            .collect::<Result<Vec<(String, String)>>>()?;
+
+        let key = |part| self.keys.checkpoint(job_id, part);
+        let mut pipe = redis::pipe();
+        pipe.atomic()
+            .set(key("depth"), checkpoint.depth)
+            .ignore()
+            .del(vec![
+                key("frontier"),
+                key("visited"),
+                key("graph"),
+                key("blocked"),
+            ])
+            .ignore();
+        if !frontier.is_empty() {
+            pipe.rpush(key("frontier"), frontier).ignore();
+        }
+        if !visited.is_empty() {
+            pipe.hset_multiple(key("visited"), &visited).ignore();
+        }
+        if !graph.is_empty() {
+            pipe.hset_multiple(key("graph"), &graph).ignore();
+        }
+        if !checkpoint.blocked.is_empty() {
+            pipe.sadd(key("blocked"), checkpoint.blocked.clone())
+                .ignore();
+        }
+        Ok(pipe.query(&*self.connection()?)?)
+    }
+
+    fn delete_checkpoint(&self, job_id: &str) -> Result<()> {
+        let keys: Vec<String> = ["depth", "frontier", "visited", "blocked", "graph"]
+            .iter()
+            .map(|suffix| self.keys.checkpoint(job_id, suffix))
+            .collect();
+        Ok(self.connection()?.del(keys)?)
+    }
+
+    fn get_job(&self, id: &str) -> Option<JobJson> {
+        self.connection()
+            .ok()?
+            .get::<String, Option<String>>(self.keys.job(id))
+            .ok()
+            .and_then(|content| content)
+            .and_then(|content| serde_json::from_str(&content).ok())
+    }
+
+    // The job and the set of active jobs are updated in a single transaction.
+    fn set_job(&self, job: &JobJson) -> Result<()> {
+        let content = to_json(job)?;
+        let key = self.keys.job(&job.id);
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        match job.state {
+            JobState::Queued | JobState::Running => pipe
+                .set(key, content)
+                .ignore()
+                .sadd(self.keys.active_jobs(), &job.id)
+                .ignore(),
+            JobState::Done | JobState::Failed => pipe
+                .set_ex(key, content, JOB_TTL)
+                .ignore()
+                .srem(self.keys.active_jobs(), &job.id)
+                .ignore(),
+        };
+        Ok(pipe.query(&*self.connection()?)?)
+    }
+
+    fn delete_job(&self, id: &str) -> Result<()> {
+        Ok(redis::pipe()
+            .atomic()
+            .del(self.keys.job(id))
+            .ignore()
+            .srem(self.keys.active_jobs(), id)
+            .ignore()
+            .query(&*self.connection()?)?)
+    }
+
+    fn get_active_jobs(&self) -> Result<HashSet<String>> {
+        Ok(self.connection()?.smembers(self.keys.active_jobs())?)
+    }
+
+    // Moves the keys written before the keys had a prefix to their place in the current layout.
+    // Keys already present in the current layout are never overwritten. The whole keyspace is
+    // only scanned once, a marker key is set when it is done.
+    fn migrate(&self) -> Result<usize> {
+        let connection = self.connection()?;
+        if connection.exists::<_, bool>(self.keys.migrated())? {
+            return Ok(0);
+        }
+        let legacy: Vec<String> = connection.scan::<String>()?.collect();
+        let mut migrated = 0;
+        for key in legacy {
+            if keys::is_legacy_domain(&key) && key_type(&connection, &key)? == "set" {
+                self.migrate_domain(&connection, &key)?;
+                migrated += 1;
+            } else if let Some(id) = key.strip_prefix("job:") {
+                if rename(&connection, &key, &self.keys.job(id))? {
+                    migrated += 1;
+                }
+            }
+        }
+        rename(
+            &connection,
+            keys::LEGACY_ACTIVE_JOBS,
+            &self.keys.active_jobs(),
+        )?;
+        connection.set::<_, _, ()>(self.keys.migrated(), 1)?;
+        Ok(migrated)
+    }
+}
+
+// Renames a key if it exists and its new name is free. Returns whether it was renamed.
+fn rename(connection: &Connection, from: &str, to: &str) -> Result<bool> {
+    if !connection.exists::<&str, bool>(from)? {
+        return Ok(false);
+    }
+    Ok(connection.rename_nx(from, to)?)
+}
+
+// Returns the type of the value of a key, "none" if it does not exist.
+fn key_type(connection: &Connection, key: &str) -> Result<String> {
+    Ok(redis::cmd("TYPE").arg(key).query(connection)?)
 }
 
+#[cfg(test)]
 mod tests {
+    // Needs a Redis server at DATABASE_URL, run with `cargo test -- --ignored`.
     #[test]
+    #[ignore]
     fn test_database_connection() {
         use super::*;
-        let connection = DataBaseConnection::new().unwrap();
-        assert!(connection.connection.is_open());
+        let storage = RedisStorage::new().unwrap();
+        assert!(storage.connection().unwrap().is_open());
     }
 }
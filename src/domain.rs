@@ -1,9 +1,70 @@
 use crate::error::{CrawlError, ErrorType};
 use crate::Result;
 
+use publicsuffix::List;
+
 use reqwest::Url;
 
-// Struct representing a domain with it base address and the originally requested url.
+use url::Host;
+
+use serde::{Deserialize, Serialize};
+
+use std::env;
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+// Where the public suffix list is read from when it is not set in the environment.
+const PUBLIC_SUFFIX_LIST: &str = "/usr/share/publicsuffix/public_suffix_list.dat";
+
+// The public suffix list, parsed by the first crawl in domain mode.
+static SUFFIX_LIST: OnceLock<List> = OnceLock::new();
+
+// How far from the host of the originally requested url a crawl can go: the host only,
+// its subdomains, every host of its registrable domain, or the urls under its path.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ScopeMode {
+    #[default]
+    Host,
+    Subdomains,
+    Domain,
+    Prefix,
+}
+
+impl FromStr for ScopeMode {
+    type Err = ();
+
+    fn from_str(value: &str) -> std::result::Result<Self, Self::Err> {
+        match value {
+            "host" => Ok(ScopeMode::Host),
+            "subdomains" => Ok(ScopeMode::Subdomains),
+            "domain" => Ok(ScopeMode::Domain),
+            "prefix" => Ok(ScopeMode::Prefix),
+            _ => Err(()),
+        }
+    }
+}
+
+// Scope settings of a crawl, with the other hosts that are treated as the requested one.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ScopeOptions {
+    #[serde(default)]
+    pub mode: ScopeMode,
+    #[serde(default)]
+    pub aliases: Vec<String>,
+}
+
+// The hosts and the path a crawl is limited to.
+#[derive(Debug, PartialEq)]
+pub struct Scope {
+    mode: ScopeMode,
+    hosts: Vec<String>,
+    prefix: String,
+}
+
+// Struct representing a domain with its host, and its port if it is not the default one,
+// and the originally requested url.
 #[derive(Debug, PartialEq)]
 pub struct Domain {
     domain_name: String,
@@ -14,10 +75,7 @@ impl Domain {
     // Checks that the originally requested url is valid, creates a Domain object.
     pub fn new(candidate_url: &str) -> Result<Self> {
         let url = Url::parse(candidate_url)?;
-        let domain = url
-            .domain()
-            .ok_or_else(|| CrawlError::new(ErrorType::WrongDomain))?
-            .to_owned();
+        let domain = host_key(&url).ok_or_else(|| CrawlError::new(ErrorType::WrongDomain))?;
         Ok(Domain {
             original_url: url,
             domain_name: domain,
@@ -32,22 +90,99 @@ impl Domain {
         self.original_url.as_str()
     }
 
-    // Completes a relative url with the base address of that domain.
-    pub fn create_url_from_path(&self, path: &str) -> Url {
-        let mut new_url = self.original_url.clone();
-        new_url.set_path(path);
-        new_url
-    }
-
     // Checks that the domain addresses correspond to each other.
     pub fn is_in_domain(&self, link: &Url) -> bool {
-        match link.domain() {
-            Some(domain) => self.domain_name == domain,
-            None => false,
-        }
+        host_key(link).as_deref() == Some(self.domain_name.as_str())
+    }
+
+    // Creates the scope of a crawl of the domain. In domain mode, the registrable domain
+    // of the host is found with the public suffix list.
+    pub fn scope(&self, options: &ScopeOptions) -> Result<Scope> {
+        let host = match (options.mode, self.original_url.host()) {
+            (ScopeMode::Domain, Some(Host::Domain(host))) => {
+                let root = registrable_domain(suffix_list()?, host);
+                match self.original_url.port() {
+                    Some(port) => format!("{}:{}", root, port),
+                    None => root,
+                }
+            }
+            _ => self.domain_name.clone(),
+        };
+
+        // The aliases are read like the host of a url with the same scheme.
+        let scheme = self.original_url.scheme();
+        let aliases = options.aliases.iter().filter_map(|alias| {
+            Url::parse(&format!("{}://{}/", scheme, alias.trim()))
+                .ok()
+                .and_then(|url| host_key(&url))
+        });
+
+        // A path ending with a file name is limited to the directory of the file.
+        let path = self.original_url.path();
+        let prefix = match path.rsplit('/').next() {
+            Some(name) if name.contains('.') => path[..path.len() - name.len()].to_owned(),
+            _ => format!("{}/", path.trim_end_matches('/')),
+        };
+        Ok(Scope {
+            mode: options.mode,
+            hosts: std::iter::once(host).chain(aliases).collect(),
+            prefix,
+        })
     }
 }
 
+// Returns the public suffix list, read from PUBLIC_SUFFIX_LIST the first time. A list that
+// could not be read is read again the next time.
+fn suffix_list() -> Result<&'static List> {
+    if let Some(list) = SUFFIX_LIST.get() {
+        return Ok(list);
+    }
+    let path = env::var("PUBLIC_SUFFIX_LIST").unwrap_or_else(|_| String::from(PUBLIC_SUFFIX_LIST));
+    let list = List::from_path(path).map_err(|_| CrawlError::new(ErrorType::PublicSuffixList))?;
+    Ok(SUFFIX_LIST.get_or_init(|| list))
+}
+
+impl Scope {
+    // Checks that a url is on one of the hosts of the scope, or one of their subdomains in
+    // subdomains and domain modes. In prefix mode, its path also has to be under the prefix.
+    pub fn contains(&self, link: &Url) -> bool {
+        let domain = match host_key(link) {
+            Some(domain) => domain,
+            None => return false,
+        };
+        let on_host = self.hosts.iter().any(|host| match self.mode {
+            ScopeMode::Host | ScopeMode::Prefix => &domain == host,
+            ScopeMode::Subdomains | ScopeMode::Domain => {
+                &domain == host || domain.ends_with(&format!(".{}", host))
+            }
+        });
+        let path = link.path();
+        on_host
+            && (self.mode != ScopeMode::Prefix
+                || path.starts_with(&self.prefix)
+                || path == self.prefix.trim_end_matches('/'))
+    }
+}
+
+// Returns the host of a url, followed by its port when it is not the default port of its
+// scheme, e.g. "docs.rs", "127.0.0.1:8080" or "[::1]:3000". The internationalized domain
+// names are in their punycode form.
+pub fn host_key(url: &Url) -> Option<String> {
+    let host = url.host_str()?;
+    Some(match url.port() {
+        Some(port) => format!("{}:{}", host, port),
+        None => host.to_owned(),
+    })
+}
+
+// Returns the registrable domain of a host, or the host itself if it has none.
+fn registrable_domain(list: &List, host: &str) -> String {
+    list.parse_domain(host)
+        .ok()
+        .and_then(|domain| domain.root().map(ToOwned::to_owned))
+        .unwrap_or_else(|| host.to_owned())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -79,10 +214,73 @@ mod tests {
         assert!(domain.is_in_domain(&in_domain));
         let other_domain = Url::parse("https://www.nytimes.com/section/world").unwrap();
         assert!(!domain.is_in_domain(&other_domain));
+    }
 
-        assert_eq!(
-            domain.create_url_from_path("uk/sports"),
-            Url::parse("https://www.theguardian.com/uk/sports").unwrap()
-        );
+    #[test]
+    fn test_domain_scope() {
+        let list = List::from_str("// ===BEGIN ICANN DOMAINS===\nuk\nco.uk\ncom\n").unwrap();
+        assert_eq!(registrable_domain(&list, "www.foo.co.uk"), "foo.co.uk");
+        assert_eq!(registrable_domain(&list, "foo.co.uk"), "foo.co.uk");
+        assert_eq!(registrable_domain(&list, "localhost"), "localhost");
+
+        let domain = Domain::new("https://www.example.com/docs/index.html").unwrap();
+        let url = |url: &str| Url::parse(url).unwrap();
+        let scope = |mode, aliases: &[&str]| {
+            domain
+                .scope(&ScopeOptions {
+                    mode,
+                    aliases: aliases.iter().map(|alias| alias.to_string()).collect(),
+                })
+                .unwrap()
+        };
+
+        let host = scope(ScopeMode::Host, &["Example.com"]);
+        assert!(host.contains(&url("https://www.example.com/about")));
+        assert!(host.contains(&url("https://example.com/about")));
+        assert!(!host.contains(&url("https://blog.example.com/")));
+
+        let subdomains = scope(ScopeMode::Subdomains, &[]);
+        assert!(subdomains.contains(&url("https://api.www.example.com/")));
+        assert!(!subdomains.contains(&url("https://example.com/")));
+        assert!(!subdomains.contains(&url("https://notwww.example.com/")));
+
+        let prefix = scope(ScopeMode::Prefix, &[]);
+        assert!(prefix.contains(&url("https://www.example.com/docs")));
+        assert!(prefix.contains(&url("https://www.example.com/docs/api/page.html")));
+        assert!(!prefix.contains(&url("https://www.example.com/docsearch")));
+        assert!(!prefix.contains(&url("https://www.example.com/")));
+    }
+
+    #[test]
+    fn test_domain_hosts() {
+        let url = |url: &str| Url::parse(url).unwrap();
+
+        let domain = Domain::new("http://127.0.0.1:8080/status").unwrap();
+        assert_eq!(domain.get_name(), "127.0.0.1:8080");
+        assert!(domain.is_in_domain(&url("http://127.0.0.1:8080/health")));
+        assert!(!domain.is_in_domain(&url("http://127.0.0.1:8081/health")));
+        assert!(!domain.is_in_domain(&url("http://127.0.0.1/health")));
+
+        let domain = Domain::new("http://[::1]:3000/").unwrap();
+        assert_eq!(domain.get_name(), "[::1]:3000");
+        assert!(domain.is_in_domain(&url("http://[0:0:0:0:0:0:0:1]:3000/page")));
+
+        let domain = Domain::new("https://localhost:443/").unwrap();
+        assert_eq!(domain.get_name(), "localhost");
+
+        let domain = Domain::new("https://Bücher.example/").unwrap();
+        assert_eq!(domain.get_name(), "xn--bcher-kva.example");
+        assert!(domain.is_in_domain(&url("https://bücher.example/katalog")));
+
+        let scope = Domain::new("http://10.0.0.5:8080/")
+            .unwrap()
+            .scope(&ScopeOptions {
+                mode: ScopeMode::Subdomains,
+                aliases: vec![String::from("staging.local:8080")],
+            })
+            .unwrap();
+        assert!(scope.contains(&url("http://10.0.0.5:8080/")));
+        assert!(scope.contains(&url("http://api.staging.local:8080/")));
+        assert!(!scope.contains(&url("http://staging.local:9090/")));
     }
 }
@@ -24,6 +24,12 @@ pub enum ErrorType {
     ScrapError,
     FetchError,
     EnvError,
+    InvalidOption,
+    JobNotFound,
+    PublicSuffixList,
+    InvalidRequest(String),
+    SnapshotNotFound,
+    QueueFull,
 }
 
 impl CrawlError {
@@ -39,6 +45,12 @@ impl CrawlError {
                 ErrorType::ScrapError => StatusCode::INTERNAL_SERVER_ERROR,
                 ErrorType::FetchError => StatusCode::BAD_GATEWAY,
                 ErrorType::EnvError => StatusCode::INTERNAL_SERVER_ERROR,
+                ErrorType::InvalidOption => StatusCode::BAD_REQUEST,
+                ErrorType::JobNotFound => StatusCode::NOT_FOUND,
+                ErrorType::PublicSuffixList => StatusCode::INTERNAL_SERVER_ERROR,
+                ErrorType::InvalidRequest(_) => StatusCode::BAD_REQUEST,
+                ErrorType::SnapshotNotFound => StatusCode::NOT_FOUND,
+                ErrorType::QueueFull => StatusCode::SERVICE_UNAVAILABLE,
             },
             kind,
         }
@@ -53,9 +65,10 @@ impl Display for CrawlError {
     }
 }
 
-impl Display for ErrorType {
-    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
-        let message = match self {
+impl ErrorType {
+    // Returns the message explaining the error to the client.
+    pub fn message(&self) -> &str {
+        match self {
             ErrorType::DataBase | ErrorType::Hyper | ErrorType::ScrapError => {
                 "Internal server error"
             }
@@ -67,9 +80,22 @@ impl Display for ErrorType {
             ErrorType::MissingParameter => "Your request should contain a domain parameter",
             ErrorType::FetchError => "Could not fetch url",
             ErrorType::EnvError => "Error with environment variables",
-        };
+            ErrorType::InvalidOption => "Invalid crawl option, please check the query parameters",
+            ErrorType::JobNotFound => "Job not found",
+            ErrorType::PublicSuffixList => "Could not read the public suffix list",
+            ErrorType::InvalidRequest(message) => message,
+            ErrorType::SnapshotNotFound => {
+                "Snapshot not found, please check the from and to parameters"
+            }
+            ErrorType::QueueFull => "Too many crawls are waiting, please try again later",
+        }
+    }
+}
+
+impl Display for ErrorType {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
         let json_struct = ErrorJson {
-            error: message.to_owned(),
+            error: self.message().to_owned(),
         };
         write!(f, "{}", serde_json::to_string(&json_struct).unwrap())
     }
@@ -110,6 +136,26 @@ impl From<redis::RedisError> for CrawlError {
     }
 }
 
+impl From<r2d2::Error> for CrawlError {
+    fn from(_err: r2d2::Error) -> Self {
+        println!("redis pool error: {}", _err);
+        CrawlError {
+            kind: ErrorType::DataBase,
+            code: StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+impl From<rusqlite::Error> for CrawlError {
+    fn from(_err: rusqlite::Error) -> Self {
+        println!("sqlite error: {}", _err);
+        CrawlError {
+            kind: ErrorType::DataBase,
+            code: StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
 impl From<std::env::VarError> for CrawlError {
     fn from(_err: std::env::VarError) -> Self {
         println!("dotenv error: {}", _err);
@@ -0,0 +1,291 @@
+use crate::json::{GraphJson, PageJson};
+
+use serde_json::{json, Map, Value};
+
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::str::FromStr;
+
+// Formats the link graph of a domain can be exported to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExportFormat {
+    Dot,
+    GraphMl,
+    JsonGraph,
+}
+
+impl ExportFormat {
+    // Returns the content type of the exported files.
+    pub fn content_type(self) -> &'static str {
+        match self {
+            ExportFormat::Dot => "text/vnd.graphviz",
+            ExportFormat::GraphMl => "application/graphml+xml",
+            ExportFormat::JsonGraph => "application/json",
+        }
+    }
+}
+
+impl FromStr for ExportFormat {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "dot" => Ok(ExportFormat::Dot),
+            "graphml" => Ok(ExportFormat::GraphMl),
+            "json" => Ok(ExportFormat::JsonGraph),
+            _ => Err(()),
+        }
+    }
+}
+
+// Information about a node of the graph, known for the crawled pages only.
+struct NodeInfo {
+    depth: Option<usize>,
+    status: Option<u16>,
+    error: Option<String>,
+}
+
+// Serializes the link graph of a domain. The nodes carry the depth and the fetch outcome
+// of the crawled pages.
+pub fn export(graph: &GraphJson, pages: &[PageJson], format: ExportFormat) -> String {
+    let pages: HashMap<&str, NodeInfo> = pages
+        .iter()
+        .map(|page| {
+            let fetch = page.fetch.as_ref();
+            let info = NodeInfo {
+                depth: page.depth,
+                status: fetch.and_then(|fetch| fetch.status),
+                error: fetch
+                    .and_then(|fetch| fetch.error)
+                    .and_then(|error| serde_json::to_value(error).ok())
+                    .and_then(|error| error.as_str().map(ToOwned::to_owned)),
+            };
+            (page.url.as_str(), info)
+        })
+        .collect();
+    match format {
+        ExportFormat::Dot => to_dot(graph, &pages),
+        ExportFormat::GraphMl => to_graphml(graph, &pages),
+        ExportFormat::JsonGraph => to_json_graph(graph, &pages),
+    }
+}
+
+fn to_dot(graph: &GraphJson, pages: &HashMap<&str, NodeInfo>) -> String {
+    let mut dot = format!("digraph {} {{\n", dot_string(&graph.domain_crawled));
+    for node in &graph.nodes {
+        let mut attributes = vec![format!("label={}", dot_string(node))];
+        if let Some(info) = pages.get(node.as_str()) {
+            attributes.extend(info.depth.map(|depth| format!("depth={}", depth)));
+            attributes.extend(info.status.map(|status| format!("status={}", status)));
+            attributes.extend(
+                info.error
+                    .as_ref()
+                    .map(|e| format!("error={}", dot_string(e))),
+            );
+        }
+        let _ = writeln!(dot, "  {} [{}];", dot_string(node), attributes.join(", "));
+    }
+    for edge in &graph.edges {
+        let element = serde_json::to_value(edge.element).unwrap_or_default();
+        let mut attributes = vec![format!(
+            "element={}",
+            dot_string(element.as_str().unwrap_or(""))
+        )];
+        attributes.extend(
+            edge.text
+                .as_ref()
+                .map(|text| format!("label={}", dot_string(text))),
+        );
+        attributes.extend(
+            edge.rel
+                .as_ref()
+                .map(|rel| format!("rel={}", dot_string(rel))),
+        );
+        let _ = writeln!(
+            dot,
+            "  {} -> {} [{}];",
+            dot_string(&edge.source),
+            dot_string(&edge.target),
+            attributes.join(", ")
+        );
+    }
+    dot.push_str("}\n");
+    dot
+}
+
+fn to_graphml(graph: &GraphJson, pages: &HashMap<&str, NodeInfo>) -> String {
+    let mut xml = String::from(concat!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+        "<graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">\n",
+        "  <key id=\"depth\" for=\"node\" attr.name=\"depth\" attr.type=\"int\"/>\n",
+        "  <key id=\"status\" for=\"node\" attr.name=\"status\" attr.type=\"int\"/>\n",
+        "  <key id=\"error\" for=\"node\" attr.name=\"error\" attr.type=\"string\"/>\n",
+        "  <key id=\"text\" for=\"edge\" attr.name=\"text\" attr.type=\"string\"/>\n",
+        "  <key id=\"rel\" for=\"edge\" attr.name=\"rel\" attr.type=\"string\"/>\n",
+        "  <key id=\"element\" for=\"edge\" attr.name=\"element\" attr.type=\"string\"/>\n",
+    ));
+    let _ = writeln!(
+        xml,
+        "  <graph id=\"{}\" edgedefault=\"directed\">",
+        xml_escape(&graph.domain_crawled)
+    );
+    for node in &graph.nodes {
+        let _ = writeln!(xml, "    <node id=\"{}\">", xml_escape(node));
+        if let Some(info) = pages.get(node.as_str()) {
+            let data = [
+                ("depth", info.depth.map(|depth| depth.to_string())),
+                ("status", info.status.map(|status| status.to_string())),
+                ("error", info.error.clone()),
+            ];
+            write_graphml_data(&mut xml, &data);
+        }
+        xml.push_str("    </node>\n");
+    }
+    for edge in &graph.edges {
+        let _ = writeln!(
+            xml,
+            "    <edge source=\"{}\" target=\"{}\">",
+            xml_escape(&edge.source),
+            xml_escape(&edge.target)
+        );
+        let element = serde_json::to_value(edge.element).unwrap_or_default();
+        let data = [
+            ("text", edge.text.clone()),
+            ("rel", edge.rel.clone()),
+            ("element", element.as_str().map(ToOwned::to_owned)),
+        ];
+        write_graphml_data(&mut xml, &data);
+        xml.push_str("    </edge>\n");
+    }
+    xml.push_str("  </graph>\n</graphml>\n");
+    xml
+}
+
+fn write_graphml_data(xml: &mut String, data: &[(&str, Option<String>)]) {
+    for (key, value) in data {
+        if let Some(value) = value {
+            let _ = writeln!(
+                xml,
+                "      <data key=\"{}\">{}</data>",
+                key,
+                xml_escape(value)
+            );
+        }
+    }
+}
+
+// Follows the JSON Graph Format, with the nodes as an object keyed by their url.
+fn to_json_graph(graph: &GraphJson, pages: &HashMap<&str, NodeInfo>) -> String {
+    let nodes: Map<String, Value> = graph
+        .nodes
+        .iter()
+        .map(|node| {
+            let mut value = json!({ "label": node });
+            if let Some(info) = pages.get(node.as_str()) {
+                value["metadata"] = json!({
+                    "depth": info.depth,
+                    "status": info.status,
+                    "error": info.error,
+                });
+            }
+            (node.clone(), value)
+        })
+        .collect();
+    let edges: Vec<Value> = graph
+        .edges
+        .iter()
+        .map(|edge| {
+            json!({
+                "source": edge.source,
+                "target": edge.target,
+                "relation": edge.element,
+                "metadata": { "text": edge.text, "rel": edge.rel },
+            })
+        })
+        .collect();
+    let document = json!({
+        "graph": {
+            "label": graph.domain_crawled,
+            "directed": true,
+            "nodes": nodes,
+            "edges": edges,
+        }
+    });
+    serde_json::to_string_pretty(&document).unwrap_or_default()
+}
+
+// Writes a DOT identifier as a quoted string.
+fn dot_string(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+fn xml_escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::json::{EdgeJson, FetchJson};
+    use crate::parsing::LinkSource;
+
+    #[test]
+    fn test_graph_export() {
+        let edge = EdgeJson {
+            source: String::from("https://docs.rs/"),
+            target: String::from("https://docs.rs/search?q=a&b=\"c\""),
+            text: Some(String::from("Search <docs>")),
+            rel: None,
+            element: LinkSource::A,
+        };
+        let mut links = HashMap::new();
+        links.insert(edge.source.clone(), vec![edge]);
+        let graph = GraphJson::new("docs.rs", links);
+        let pages = vec![PageJson {
+            url: String::from("https://docs.rs/"),
+            depth: Some(0),
+            sources: vec![],
+            referrers: vec![],
+            sitemap: None,
+            fetch: Some(FetchJson {
+                status: Some(200),
+                error: None,
+                content_type: None,
+                response_time_ms: 10,
+                size: 100,
+                etag: None,
+                last_modified: None,
+                content_hash: None,
+            }),
+            metrics: None,
+            change: None,
+        }];
+
+        let dot = export(&graph, &pages, ExportFormat::Dot);
+        assert!(dot.starts_with("digraph \"docs.rs\" {\n"));
+        assert!(
+            dot.contains("\"https://docs.rs/\" [label=\"https://docs.rs/\", depth=0, status=200];")
+        );
+        assert!(dot.contains(concat!(
+            r#""https://docs.rs/" -> "https://docs.rs/search?q=a&b=\"c\"" "#,
+            r#"[element="a", label="Search <docs>"];"#
+        )));
+
+        let graphml = export(&graph, &pages, ExportFormat::GraphMl);
+        assert!(graphml.contains("<node id=\"https://docs.rs/search?q=a&amp;b=&quot;c&quot;\">"));
+        assert!(graphml.contains("<data key=\"text\">Search &lt;docs&gt;</data>"));
+
+        let json: Value =
+            serde_json::from_str(&export(&graph, &pages, ExportFormat::JsonGraph)).unwrap();
+        assert_eq!(
+            json["graph"]["nodes"]["https://docs.rs/"]["metadata"]["status"],
+            200
+        );
+        assert_eq!(json["graph"]["edges"][0]["relation"], "a");
+        assert_eq!("graphml".parse(), Ok(ExportFormat::GraphMl));
+    }
+}
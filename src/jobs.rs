@@ -0,0 +1,337 @@
+use crate::crawler::crawl;
+use crate::domain::Domain;
+use crate::error::{CrawlError, ErrorType};
+use crate::json::{JobJson, JobState, ProgressJson};
+use crate::options::CrawlOptions;
+use crate::storage::{SharedStorage, Storage};
+use crate::Result;
+
+use rayon::{ThreadPool, ThreadPoolBuilder};
+
+use std::env;
+use std::panic::{self, AssertUnwindSafe};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Number of crawls running at the same time when JOB_WORKERS is not set.
+const JOB_WORKERS: usize = 4;
+
+// Number of jobs waiting for a worker when JOB_QUEUE_SIZE is not set.
+const JOB_QUEUE_SIZE: usize = 100;
+
+// Counter making the job ids unique inside the same nanosecond.
+static JOB_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+// A job waiting for a worker.
+type QueuedJob = (String, Domain);
+
+// Runs the crawl jobs on a fixed number of worker threads, away from the threads of the
+// server. The jobs wait for a worker in a bounded queue, and the requests of the crawls
+// are made from a thread pool of their own.
+pub struct JobQueue {
+    storage: SharedStorage,
+    sender: SyncSender<QueuedJob>,
+    receiver: Mutex<Receiver<QueuedJob>>,
+}
+
+impl JobQueue {
+    // Starts the workers, as many as JOB_WORKERS, with a queue of JOB_QUEUE_SIZE jobs and
+    // CRAWL_THREADS threads for the crawls, one per CPU by default.
+    pub fn from_env(storage: SharedStorage) -> Result<Arc<Self>> {
+        JobQueue::new(
+            storage,
+            env_number("JOB_WORKERS").unwrap_or(JOB_WORKERS).max(1),
+            env_number("JOB_QUEUE_SIZE").unwrap_or(JOB_QUEUE_SIZE),
+            env_number("CRAWL_THREADS").unwrap_or(0),
+        )
+    }
+
+    // Starts the workers. With 0 threads, the crawls get one thread per CPU.
+    pub fn new(
+        storage: SharedStorage,
+        workers: usize,
+        queue_size: usize,
+        threads: usize,
+    ) -> Result<Arc<Self>> {
+        let pool = ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .thread_name(|index| format!("crawl-{}", index))
+            .build()
+            .map_err(|e| {
+                println!("crawl threads not started: {}", e);
+                CrawlError::new(ErrorType::EnvError)
+            })?;
+        let pool = Arc::new(pool);
+        let (sender, receiver) = mpsc::sync_channel(queue_size);
+        let queue = Arc::new(JobQueue {
+            storage,
+            sender,
+            receiver: Mutex::new(receiver),
+        });
+        for index in 0..workers {
+            let (queue, pool) = (queue.clone(), pool.clone());
+            thread::Builder::new()
+                .name(format!("job-worker-{}", index))
+                .spawn(move || queue.work(&pool))
+                .map_err(|e| {
+                    println!("job worker not started: {}", e);
+                    CrawlError::new(ErrorType::EnvError)
+                })?;
+        }
+        Ok(queue)
+    }
+
+    // Registers a crawl job for the domain and queues it. Returns the job in its queued
+    // state right away, or an error if too many jobs are already waiting.
+    pub fn start_job(&self, domain: Domain, options: CrawlOptions) -> Result<JobJson> {
+        self.queue_job(domain, options, false)
+    }
+
+    // Registers and queues a job, which may be the background refresh of a stale domain.
+    fn queue_job(&self, domain: Domain, options: CrawlOptions, refresh: bool) -> Result<JobJson> {
+        let job = JobJson {
+            id: new_job_id(),
+            state: JobState::Queued,
+            url: domain.get_original_url().to_owned(),
+            options,
+            progress: ProgressJson::default(),
+            refresh,
+            result: None,
+            error: None,
+        };
+        // The job is saved before it is queued, as a worker may pick it up right away.
+        self.storage.set_job(&job)?;
+        // The queue owns its receiver, so sending can only fail when the queue is full. The
+        // rejected job is not kept.
+        if self.sender.try_send((job.id.clone(), domain)).is_err() {
+            self.storage.delete_job(&job.id)?;
+            return Err(CrawlError::new(ErrorType::QueueFull));
+        }
+        Ok(job)
+    }
+
+    // Starts again the jobs that were queued or running when the server stopped. The crawls
+    // resume from their last checkpoint. The jobs that do not fit in the queue fail instead
+    // of holding up the start of the server.
+    pub fn recover_jobs(&self) -> Result<()> {
+        for id in self.storage.get_active_jobs()? {
+            let mut job = match self.storage.get_job(&id) {
+                Some(job) => job,
+                None => continue,
+            };
+            let domain = match Domain::new(&job.url) {
+                Ok(domain) => domain,
+                Err(e) => {
+                    self.storage.delete_checkpoint(&id)?;
+                    job.state = JobState::Failed;
+                    job.error = Some(e.kind.message().to_owned());
+                    self.storage.set_job(&job)?;
+                    continue;
+                }
+            };
+            match self.sender.try_send((id, domain)) {
+                Ok(()) => println!("job {} was interrupted, resuming it", job.id),
+                Err(TrySendError::Full((id, domain)))
+                | Err(TrySendError::Disconnected((id, domain))) => {
+                    println!("job {} was interrupted, the queue is full", id);
+                    self.fail_job(&id, &domain, ErrorType::QueueFull)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Runs the queued jobs one after the other.
+    fn work(&self, pool: &ThreadPool) {
+        loop {
+            // The lock is only held while waiting for the next job.
+            let next = self
+                .receiver
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .recv();
+            let (id, domain) = match next {
+                Ok(next) => next,
+                Err(_) => return,
+            };
+            // A crawl that panics fails its job without stopping the worker.
+            let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                pool.install(|| self.run_job(&id, &domain))
+            }))
+            .unwrap_or_else(|_| self.fail_job(&id, &domain, ErrorType::ScrapError));
+            if let Err(e) = result {
+                println!("job {} could not be updated: {}", id, e.kind.message());
+            }
+        }
+    }
+
+    // Crawls the domain with the options of the job and saves the progress and the outcome
+    // of the job. When the crawl returns a stale set of urls, the domain is refreshed by
+    // another job.
+    fn run_job(&self, id: &str, domain: &Domain) -> Result<()> {
+        let storage = &self.storage;
+        let started = storage
+            .get_job(id)
+            .ok_or_else(|| CrawlError::new(ErrorType::JobNotFound))
+            .and_then(|mut job| {
+                job.state = JobState::Running;
+                storage.set_job(&job).map(|_| job)
+            });
+        let mut job = match started {
+            Ok(job) => job,
+            // A job that cannot be started fails instead of staying queued.
+            Err(e) => return self.fail_job(id, domain, e.kind),
+        };
+
+        let options = job.options.clone();
+        let result = crawl(storage, domain, &options, Some(id), &mut |progress| {
+            job.progress = progress.clone();
+            if let Err(e) = storage.set_job(&job) {
+                println!("job {} progress not saved: {}", job.id, e.kind.message());
+            }
+        });
+
+        match result {
+            Ok(mut json) => {
+                if json.stale {
+                    json.refresh_job = self.refresh(domain, &options).unwrap_or_else(|e| {
+                        println!(
+                            "{} not refreshed: {}",
+                            json.domain_crawled,
+                            e.kind.message()
+                        );
+                        None
+                    });
+                }
+                job.state = JobState::Done;
+                job.result = Some(json);
+            }
+            Err(e) => {
+                // A failed crawl is not resumed, so its checkpoint goes away with it.
+                storage.delete_checkpoint(id)?;
+                if job.refresh {
+                    storage.unlock_refresh(&domain.get_name())?;
+                }
+                job.state = JobState::Failed;
+                job.error = Some(e.kind.message().to_owned());
+            }
+        }
+        storage.set_job(&job)
+    }
+
+    // Marks a job as failed and drops the checkpoint of its crawl. A failed refresh lets
+    // the domain be refreshed again.
+    fn fail_job(&self, id: &str, domain: &Domain, kind: ErrorType) -> Result<()> {
+        self.storage.delete_checkpoint(id)?;
+        let mut job = get_job(self.storage.as_ref(), id)?;
+        if job.refresh {
+            self.storage.unlock_refresh(&domain.get_name())?;
+        }
+        job.state = JobState::Failed;
+        job.error = Some(kind.message().to_owned());
+        self.storage.set_job(&job)
+    }
+
+    // Starts a job crawling the domain again, unless it is already being refreshed. Returns
+    // the id of the job.
+    fn refresh(&self, domain: &Domain, options: &CrawlOptions) -> Result<Option<String>> {
+        let name = domain.get_name();
+        if !self.storage.lock_refresh(&name)? {
+            return Ok(None);
+        }
+        let options = CrawlOptions {
+            force_refresh: true,
+            stale_while_revalidate: false,
+            ..options.clone()
+        };
+        let job = Domain::new(domain.get_original_url())
+            .and_then(|domain| self.queue_job(domain, options, true));
+        if job.is_err() {
+            self.storage.unlock_refresh(&name)?;
+        }
+        job.map(|job| Some(job.id))
+    }
+}
+
+// Returns a job from its id.
+pub fn get_job(storage: &dyn Storage, id: &str) -> Result<JobJson> {
+    storage
+        .get_job(id)
+        .ok_or_else(|| CrawlError::new(ErrorType::JobNotFound))
+}
+
+// Creates an id from the current time, the process id and a counter.
+fn new_job_id() -> String {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_nanos())
+        .unwrap_or_default();
+    format!(
+        "{:x}-{:x}-{:x}",
+        nanos,
+        process::id(),
+        JOB_COUNTER.fetch_add(1, Ordering::SeqCst)
+    )
+}
+
+// Reads a number from an environment variable, if it is set.
+fn env_number(name: &str) -> Option<usize> {
+    env::var(name).ok().and_then(|value| value.parse().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::MemoryStorage;
+
+    #[test]
+    fn test_job_queue() {
+        let storage: SharedStorage = Arc::new(MemoryStorage::new());
+        // Without workers, the jobs stay in the queue.
+        let jobs = JobQueue::new(storage.clone(), 0, 1, 1).unwrap();
+        let options = CrawlOptions::default();
+        let domain = || Domain::new("https://example.com/").unwrap();
+
+        let queued = jobs.start_job(domain(), options.clone()).unwrap();
+        assert_eq!(queued.state, JobState::Queued);
+        let stored = get_job(storage.as_ref(), &queued.id).unwrap();
+        assert_eq!(stored.state, JobState::Queued);
+
+        let error = jobs.start_job(domain(), options).unwrap_err();
+        assert_eq!(error.kind, ErrorType::QueueFull);
+        assert_eq!(error.code, hyper::StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(storage.get_active_jobs().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_job_recovery() {
+        let storage: SharedStorage = Arc::new(MemoryStorage::new());
+        for id in &["1f-1", "1f-2"] {
+            let job = JobJson {
+                id: id.to_string(),
+                state: JobState::Running,
+                url: String::from("https://example.com/"),
+                options: CrawlOptions::default(),
+                progress: ProgressJson::default(),
+                refresh: false,
+                result: None,
+                error: None,
+            };
+            storage.set_job(&job).unwrap();
+        }
+        // Only one of the interrupted jobs fits in the queue, the other one fails.
+        let jobs = JobQueue::new(storage.clone(), 0, 1, 1).unwrap();
+        jobs.recover_jobs().unwrap();
+        let states: Vec<JobState> = ["1f-1", "1f-2"]
+            .iter()
+            .map(|id| get_job(storage.as_ref(), id).unwrap().state)
+            .collect();
+        assert!(states.contains(&JobState::Running));
+        assert!(states.contains(&JobState::Failed));
+        assert_eq!(storage.get_active_jobs().unwrap().len(), 1);
+    }
+}
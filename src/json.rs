@@ -1,23 +1,57 @@
-use serde::Serialize;
+use crate::canonical::TrailingSlash;
+use crate::domain::ScopeMode;
+use crate::options::CrawlOptions;
+use crate::parsing::LinkSource;
+use crate::rules::UrlRules;
 
-use std::collections::HashSet;
+use serde::{Deserialize, Serialize};
+
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
 use std::fmt;
 
 // JSON formatted object that is responsible for the transformation of a urls set into the response body.
-#[derive(Serialize, Debug)]
+#[derive(Serialize, Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct UrlsJson {
 	pub nb_urls: usize,
 	pub domain_crawled: String,
-	pub urls: Vec<String>,
+	pub urls: Vec<PageJson>,
+	pub blocked_urls: Vec<String>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub stats: Option<StatsJson>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub sitemap: Option<SitemapReportJson>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub rules: Option<UrlRules>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub crawled_at: Option<u64>,
+	#[serde(default, skip_serializing_if = "std::ops::Not::not")]
+	pub stale: bool,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub refresh_job: Option<String>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub delta: Option<DeltaJson>,
 }
 
 impl UrlsJson {
-	pub fn new(domain_name: &str, set: HashSet<String>) -> Self {
+	pub fn new(domain_name: &str, mut pages: Vec<PageJson>, blocked_set: HashSet<String>) -> Self {
+		// Lists the urls level by level, the closest ones to the original url first and the
+		// ones of unknown depth last.
+		let key = |page: &PageJson| (page.depth.is_none(), page.depth, page.url.clone());
+		pages.sort_by_cached_key(key);
 		UrlsJson {
-			nb_urls: set.len(),
+			nb_urls: pages.len(),
 			domain_crawled: domain_name.to_owned(),
-			urls: set.into_iter().collect(),
+			urls: pages,
+			blocked_urls: blocked_set.into_iter().collect(),
+			stats: None,
+			sitemap: None,
+			rules: None,
+			crawled_at: None,
+			stale: false,
+			refresh_job: None,
+			delta: None,
 		}
 	}
 }
@@ -31,6 +65,323 @@ impl fmt::Display for UrlsJson {
 	}
 }
 
+// Information about a crawled url. The depth is the number of clicks needed to reach it from
+// the originally requested url, unknown for the domains crawled before it was recorded.
+// The sources are the kinds of elements the links to the url were found in, the referrers are
+// the pages these links were found in, and the sitemap
+// information is only given for the urls listed in the sitemaps of the domain. The fetch
+// object tells how the request of the url went, and the metrics how it is linked to. When
+// the domain was crawled before, the change tells how the url compares with that crawl.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct PageJson {
+	pub url: String,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub depth: Option<usize>,
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	pub sources: Vec<LinkSource>,
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	pub referrers: Vec<String>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub sitemap: Option<SitemapJson>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub fetch: Option<FetchJson>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub metrics: Option<MetricsJson>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub change: Option<PageChange>,
+}
+
+impl PageJson {
+	// Records a link to the url, with the kind of element and the page it was found in.
+	pub fn add_link(&mut self, source: Option<LinkSource>, referrer: Option<String>) {
+		if let Some(source) = source.filter(|source| !self.sources.contains(source)) {
+			self.sources.push(source);
+		}
+		if let Some(referrer) = referrer.filter(|referrer| !self.referrers.contains(referrer)) {
+			self.referrers.push(referrer);
+		}
+	}
+
+	// Checks if the request of the url failed or got a 4xx or 5xx response.
+	pub fn is_broken(&self) -> bool {
+		self.fetch.as_ref().map(|fetch| fetch.error.is_some()).unwrap_or(false)
+	}
+}
+
+// How a url compares with the previous crawl of its domain: not reached by it, or reached
+// with a different or the same content.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum PageChange {
+	New,
+	Changed,
+	Unchanged,
+}
+
+// Outcome of the request of a url: the status and the content type of the response or the
+// kind of error that happened, the response time and the size of the body in bytes. The
+// validators of the response and the SHA-256 hash of its body tell if the page changed.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct FetchJson {
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub status: Option<u16>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub error: Option<FetchError>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub content_type: Option<String>,
+	pub response_time_ms: u64,
+	pub size: usize,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub etag: Option<String>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub last_modified: Option<String>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub content_hash: Option<String>,
+}
+
+// Kinds of errors of a request. Status is for the responses with a 4xx or 5xx status code.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum FetchError {
+	Status,
+	Timeout,
+	Connect,
+	Tls,
+	Redirect,
+	Body,
+	Other,
+}
+
+// Link metrics of a crawled url: its internal PageRank, the number of crawled pages linking to
+// it and that it links to, and the page whose link led the crawler to it.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct MetricsJson {
+	pub page_rank: f64,
+	pub inlinks: usize,
+	pub outlinks: usize,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub discovered_from: Option<String>,
+}
+
+// JSON format for the metrics/pages response: the metrics of the urls of a crawled domain,
+// highest PageRank first.
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct PageMetricsJson {
+	pub nb_urls: usize,
+	pub domain_crawled: String,
+	pub pages: Vec<PageMetricJson>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct PageMetricJson {
+	pub url: String,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub depth: Option<usize>,
+	#[serde(flatten)]
+	pub metrics: MetricsJson,
+}
+
+impl PageMetricsJson {
+	pub fn new(
+		domain_name: &str,
+		pages: &[PageJson],
+		mut metrics: HashMap<String, MetricsJson>,
+	) -> Self {
+		let mut pages: Vec<PageMetricJson> = pages
+			.iter()
+			.filter_map(|page| {
+				metrics.remove(&page.url).map(|metrics| PageMetricJson {
+					url: page.url.clone(),
+					depth: page.depth,
+					metrics,
+				})
+			})
+			.collect();
+		pages.sort_by(|a, b| {
+			b.metrics
+				.page_rank
+				.partial_cmp(&a.metrics.page_rank)
+				.unwrap_or(Ordering::Equal)
+				.then_with(|| a.url.cmp(&b.url))
+		});
+		PageMetricsJson {
+			nb_urls: pages.len(),
+			domain_crawled: domain_name.to_owned(),
+			pages,
+		}
+	}
+}
+
+impl fmt::Display for PageMetricsJson {
+	fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+		let message = serde_json::to_string_pretty(self).unwrap_or_else(|_| {
+			"An error occured while serializing the answer to json format".to_owned()
+		});
+		write!(fmt, "{}", message)
+	}
+}
+
+// Metadata of a url given by a sitemap.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct SitemapJson {
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub lastmod: Option<String>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub priority: Option<f64>,
+}
+
+// Comparison of the urls listed in the sitemaps of a domain with the urls reached by its crawl,
+// only available in the response to the crawl request.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct SitemapReportJson {
+	pub sitemaps: Vec<String>,
+	pub listed_urls: usize,
+	pub reached_urls: usize,
+	pub not_reached: Vec<String>,
+	pub not_listed: Vec<String>,
+}
+
+// Differences between a crawl and the previous crawl of the domain, only available in the
+// response to the crawl request.
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct DeltaJson {
+	pub new: Vec<String>,
+	pub changed: Vec<String>,
+	pub unchanged: Vec<String>,
+	pub removed: Vec<String>,
+}
+
+impl DeltaJson {
+	pub fn new(pages: &[PageJson], previous: &HashMap<String, PageJson>) -> Self {
+		let mut delta = DeltaJson::default();
+		for page in pages {
+			match page.change {
+				Some(PageChange::New) => delta.new.push(page.url.clone()),
+				Some(PageChange::Changed) => delta.changed.push(page.url.clone()),
+				Some(PageChange::Unchanged) => delta.unchanged.push(page.url.clone()),
+				None => (),
+			}
+		}
+		let reached: HashSet<&str> = pages.iter().map(|page| page.url.as_str()).collect();
+		delta.removed = previous
+			.keys()
+			.filter(|url| !reached.contains(url.as_str()))
+			.cloned()
+			.collect();
+		delta.new.sort();
+		delta.changed.sort();
+		delta.unchanged.sort();
+		delta.removed.sort();
+		delta
+	}
+}
+
+// Statistics of a crawl, only available in the response to the crawl request.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct StatsJson {
+	pub duration_ms: u64,
+	pub politeness: PolitenessJson,
+}
+
+// Statistics of the politeness layer: requests sent, throttling and delays per host.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct PolitenessJson {
+	pub requests: usize,
+	pub throttled_responses: usize,
+	pub failed_requests: usize,
+	pub total_wait_ms: u64,
+	pub min_delay_ms: u64,
+	pub max_delay_reached_ms: u64,
+	pub current_delays_ms: HashMap<String, u64>,
+	pub max_in_flight: usize,
+}
+
+// State of a crawl job.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum JobState {
+	Queued,
+	Running,
+	Done,
+	Failed,
+}
+
+// JSON format for a crawl job, with its progress and its result once it is done.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct JobJson {
+	pub id: String,
+	pub state: JobState,
+	pub url: String,
+	pub options: CrawlOptions,
+	pub progress: ProgressJson,
+	// Whether the job refreshes a stale domain in the background.
+	#[serde(default)]
+	pub refresh: bool,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub result: Option<UrlsJson>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub error: Option<String>,
+}
+
+impl fmt::Display for JobJson {
+	fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+		let message = serde_json::to_string_pretty(self).unwrap_or_else(|_| {
+			"An error occured while serializing the answer to json format".to_owned()
+		});
+		write!(fmt, "{}", message)
+	}
+}
+
+// JSON body of a crawl request: the url to crawl and the settings of the crawl, named like
+// the query parameters. The settings that are not given keep their default values.
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct CrawlRequestJson {
+	pub url: String,
+	pub max_pages: Option<usize>,
+	pub max_depth: Option<usize>,
+	pub user_agent: Option<String>,
+	pub scope: Option<ScopeMode>,
+	pub aliases: Option<Vec<String>>,
+	#[serde(default)]
+	pub include: Vec<String>,
+	#[serde(default)]
+	pub exclude: Vec<String>,
+	pub link_sources: Option<Vec<LinkSource>>,
+	pub sitemaps: Option<bool>,
+	pub sitemap_only: Option<bool>,
+	pub min_delay_ms: Option<u64>,
+	pub max_delay_ms: Option<u64>,
+	pub max_in_flight: Option<usize>,
+	pub trailing_slash: Option<TrailingSlash>,
+	pub sort_query: Option<bool>,
+	pub ignored_params: Option<Vec<String>>,
+	pub force_refresh: Option<bool>,
+	pub ttl_secs: Option<u64>,
+	pub stale_while_revalidate: Option<bool>,
+}
+
+// Counters of a running crawl, updated after each level of the exploration.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ProgressJson {
+	pub depth: usize,
+	pub visited_urls: usize,
+	pub blocked_urls: usize,
+	pub queued_urls: usize,
+}
+
 // JSON format for the nb-urls response.
 #[derive(Serialize, Debug)]
 #[serde(rename_all = "camelCase")]
@@ -48,8 +399,283 @@ impl fmt::Display for NbJson {
 	}
 }
 
+// Outcome of the request of a url kept in a snapshot of a crawl.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct SnapshotPageJson {
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub status: Option<u16>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub error: Option<FetchError>,
+}
+
+impl SnapshotPageJson {
+	pub fn new(page: &PageJson) -> Self {
+		SnapshotPageJson {
+			status: page.fetch.as_ref().and_then(|fetch| fetch.status),
+			error: page.fetch.as_ref().and_then(|fetch| fetch.error),
+		}
+	}
+}
+
+// JSON format for the diff response: the urls added and removed between two snapshots of the
+// crawls of a domain, and the urls whose status changed. The snapshots are named after the
+// time of their crawl, and all the stored snapshots of the domain are listed.
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct DiffJson {
+	pub domain_crawled: String,
+	pub from: u64,
+	pub to: u64,
+	pub added: Vec<String>,
+	pub removed: Vec<String>,
+	pub changed: Vec<StatusChangeJson>,
+	pub snapshots: Vec<u64>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct StatusChangeJson {
+	pub url: String,
+	pub from: SnapshotPageJson,
+	pub to: SnapshotPageJson,
+}
+
+impl DiffJson {
+	pub fn new(
+		domain_name: &str,
+		(from, before): (u64, &HashMap<String, SnapshotPageJson>),
+		(to, after): (u64, &HashMap<String, SnapshotPageJson>),
+		snapshots: Vec<u64>,
+	) -> Self {
+		let missing = |urls: &HashMap<String, SnapshotPageJson>, other: &HashMap<_, _>| {
+			let mut missing: Vec<String> =
+				urls.keys().filter(|url| !other.contains_key(*url)).cloned().collect();
+			missing.sort();
+			missing
+		};
+		let mut changed: Vec<StatusChangeJson> = after
+			.iter()
+			.filter_map(|(url, page)| {
+				let previous = before.get(url).filter(|previous| *previous != page)?;
+				Some(StatusChangeJson {
+					url: url.clone(),
+					from: previous.clone(),
+					to: page.clone(),
+				})
+			})
+			.collect();
+		changed.sort_by(|a, b| a.url.cmp(&b.url));
+		DiffJson {
+			domain_crawled: domain_name.to_owned(),
+			from,
+			to,
+			added: missing(after, before),
+			removed: missing(before, after),
+			changed,
+			snapshots,
+		}
+	}
+}
+
+impl fmt::Display for DiffJson {
+	fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+		let message = serde_json::to_string_pretty(self).unwrap_or_else(|_| {
+			"An error occured while serializing the answer to json format".to_owned()
+		});
+		write!(fmt, "{}", message)
+	}
+}
+
+// JSON format for the broken-links response: the urls of a crawled domain that could not be
+// fetched or got a 4xx or 5xx response, with the pages linking to them.
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct BrokenLinksJson {
+	pub nb_broken_links: usize,
+	pub domain_crawled: String,
+	pub broken_links: Vec<BrokenLinkJson>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct BrokenLinkJson {
+	pub url: String,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub status: Option<u16>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub error: Option<FetchError>,
+	pub referrers: Vec<String>,
+}
+
+impl BrokenLinksJson {
+	pub fn new(domain_name: &str, pages: &[PageJson]) -> Self {
+		let mut broken_links: Vec<BrokenLinkJson> = pages
+			.iter()
+			.filter(|page| page.is_broken())
+			.map(|page| BrokenLinkJson {
+				url: page.url.clone(),
+				status: page.fetch.as_ref().and_then(|fetch| fetch.status),
+				error: page.fetch.as_ref().and_then(|fetch| fetch.error),
+				referrers: page.referrers.clone(),
+			})
+			.collect();
+		broken_links.sort_by(|a, b| a.url.cmp(&b.url));
+		BrokenLinksJson {
+			nb_broken_links: broken_links.len(),
+			domain_crawled: domain_name.to_owned(),
+			broken_links,
+		}
+	}
+}
+
+impl fmt::Display for BrokenLinksJson {
+	fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+		let message = serde_json::to_string_pretty(self).unwrap_or_else(|_| {
+			"An error occured while serializing the answer to json format".to_owned()
+		});
+		write!(fmt, "{}", message)
+	}
+}
+
+// JSON format for the graph response: the pages of a crawled domain and the links between them.
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct GraphJson {
+	pub nb_nodes: usize,
+	pub nb_edges: usize,
+	pub domain_crawled: String,
+	pub nodes: Vec<String>,
+	pub edges: Vec<EdgeJson>,
+}
+
+// A link from a page to another url, with its text, its rel attribute and the kind of element
+// it was found in.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct EdgeJson {
+	pub source: String,
+	pub target: String,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub text: Option<String>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub rel: Option<String>,
+	pub element: LinkSource,
+}
+
+impl GraphJson {
+	// Builds the graph from the links found in each page. The nodes are the pages and the
+	// targets of their links.
+	pub fn new(domain_name: &str, links: HashMap<String, Vec<EdgeJson>>) -> Self {
+		let mut nodes: HashSet<String> = links.keys().cloned().collect();
+		let mut edges: Vec<EdgeJson> = links.into_values().flatten().collect();
+		edges.sort_by(|a, b| (&a.source, &a.target).cmp(&(&b.source, &b.target)));
+		nodes.extend(edges.iter().map(|edge| edge.target.clone()));
+		let mut nodes: Vec<String> = nodes.into_iter().collect();
+		nodes.sort();
+		GraphJson {
+			nb_nodes: nodes.len(),
+			nb_edges: edges.len(),
+			domain_crawled: domain_name.to_owned(),
+			nodes,
+			edges,
+		}
+	}
+}
+
+impl fmt::Display for GraphJson {
+	fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+		let message = serde_json::to_string_pretty(self).unwrap_or_else(|_| {
+			"An error occured while serializing the answer to json format".to_owned()
+		});
+		write!(fmt, "{}", message)
+	}
+}
+
 // JSON format for the CrawlError structs.
 #[derive(Serialize, Debug)]
 pub struct ErrorJson {
 	pub error: String,
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::storage::bare_page;
+
+	// A page fetched with that status, broken if the request failed.
+	fn fetched(url: &str, status: Option<u16>, error: Option<FetchError>) -> PageJson {
+		let mut page = bare_page(url.to_owned());
+		page.fetch = Some(FetchJson {
+			status,
+			error,
+			content_type: None,
+			response_time_ms: 10,
+			size: 0,
+			etag: None,
+			last_modified: None,
+			content_hash: None,
+		});
+		page
+	}
+
+	#[test]
+	fn test_broken_links() {
+		let ok = fetched("https://docs.rs/", Some(200), None);
+		let mut missing = fetched("https://docs.rs/missing", Some(404), Some(FetchError::Status));
+		missing.referrers = vec![String::from("https://docs.rs/")];
+		let down = fetched("https://docs.rs/down", None, Some(FetchError::Connect));
+		assert!(!ok.is_broken());
+		assert!(missing.is_broken());
+		assert!(down.is_broken());
+		assert!(!bare_page(String::from("https://docs.rs/about")).is_broken());
+
+		let json = BrokenLinksJson::new("docs.rs", &[missing, ok, down]);
+		assert_eq!(json.nb_broken_links, 2);
+		assert_eq!(json.domain_crawled, "docs.rs");
+		assert_eq!(json.broken_links[0].url, "https://docs.rs/down");
+		assert_eq!(json.broken_links[0].status, None);
+		assert_eq!(json.broken_links[0].error, Some(FetchError::Connect));
+		assert_eq!(json.broken_links[1].url, "https://docs.rs/missing");
+		assert_eq!(json.broken_links[1].status, Some(404));
+		assert_eq!(json.broken_links[1].referrers, vec!["https://docs.rs/"]);
+	}
+
+	#[test]
+	fn test_snapshot_diff() {
+		let snapshot = |pages: &[(&str, Option<u16>, Option<FetchError>)]| {
+			pages
+				.iter()
+				.map(|(url, status, error)| {
+					let page = fetched(url, *status, *error);
+					(page.url.clone(), SnapshotPageJson::new(&page))
+				})
+				.collect::<HashMap<_, _>>()
+		};
+		let before = snapshot(&[
+			("https://docs.rs/", Some(200), None),
+			("https://docs.rs/old", Some(200), None),
+			("https://docs.rs/moved", Some(200), None),
+			("https://docs.rs/down", Some(200), None),
+		]);
+		let after = snapshot(&[
+			("https://docs.rs/", Some(200), None),
+			("https://docs.rs/new", Some(200), None),
+			("https://docs.rs/moved", Some(404), Some(FetchError::Status)),
+			("https://docs.rs/down", None, Some(FetchError::Connect)),
+		]);
+
+		let diff = DiffJson::new("docs.rs", (10, &before), (20, &after), vec![10, 20]);
+		assert_eq!((diff.from, diff.to), (10, 20));
+		assert_eq!(diff.added, vec!["https://docs.rs/new"]);
+		assert_eq!(diff.removed, vec!["https://docs.rs/old"]);
+		let changed: Vec<&str> = diff.changed.iter().map(|change| change.url.as_str()).collect();
+		assert_eq!(changed, vec!["https://docs.rs/down", "https://docs.rs/moved"]);
+		assert_eq!(diff.changed[1].from.status, Some(200));
+		assert_eq!(diff.changed[1].to.status, Some(404));
+		assert_eq!(diff.changed[0].to.error, Some(FetchError::Connect));
+
+		let same = DiffJson::new("docs.rs", (20, &after), (20, &after), vec![10, 20]);
+		assert!(same.added.is_empty() && same.removed.is_empty() && same.changed.is_empty());
+	}
+}
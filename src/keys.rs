@@ -0,0 +1,142 @@
+// Layout of the keys stored in Redis. Every key starts with the prefix of the deployment,
+// REDIS_KEY_PREFIX or "crawler" by default, followed by the version of the layout:
+//
+//   {prefix}:v1:domain:{name}:urls              set of the crawled urls of a domain
+//   {prefix}:v1:domain:{name}:pages             hash of the information about each url
+//   {prefix}:v1:domain:{name}:blocked           set of the urls blocked by robots.txt
+//   {prefix}:v1:domain:{name}:graph             hash of the links found in each page
+//   {prefix}:v1:domain:{name}:meta              hash of the rules, the options and the time of the crawl
+//   {prefix}:v1:domain:{name}:robots            cached robots.txt rules
+//   {prefix}:v1:domain:{name}:refreshing        lock of a background refresh
+//   {prefix}:v1:domain:{name}:snapshots         sorted set of the times of the snapshots
+//   {prefix}:v1:domain:{name}:snapshot:{time}   hash of the status of each url in a snapshot
+//   {prefix}:v1:job:{id}                        state of a crawl job
+//   {prefix}:v1:job:{id}:crawl:{part}           checkpoint of the crawl of a running job
+//   {prefix}:v1:jobs:active                     set of the ids of the unfinished jobs
+//   {prefix}:v1:migrated                        set once the legacy keys were moved
+const VERSION: &str = "v1";
+
+// Prefix of the keys when REDIS_KEY_PREFIX is not set.
+pub const DEFAULT_PREFIX: &str = "crawler";
+
+// Builds the keys of a deployment from its prefix.
+pub struct Keys {
+    root: String,
+}
+
+impl Keys {
+    pub fn new(prefix: &str) -> Self {
+        Keys {
+            root: format!("{}:{}", prefix, VERSION),
+        }
+    }
+
+    // Returns the key of a piece of information about a domain, e.g.
+    // domain("docs.rs", "urls").
+    pub fn domain(&self, name: &str, part: &str) -> String {
+        format!("{}:domain:{}:{}", self.root, name, part)
+    }
+
+    // Returns the key of a snapshot of the crawls of a domain.
+    pub fn snapshot(&self, name: &str, crawled_at: u64) -> String {
+        self.domain(name, &format!("snapshot:{}", crawled_at))
+    }
+
+    // Returns the key of a part of the checkpoint of the crawl of a job.
+    pub fn checkpoint(&self, job_id: &str, part: &str) -> String {
+        format!("{}:crawl:{}", self.job(job_id), part)
+    }
+
+    // Returns the key of the state of a job.
+    pub fn job(&self, id: &str) -> String {
+        format!("{}:job:{}", self.root, id)
+    }
+
+    // Returns the key of the set of the ids of the unfinished jobs.
+    pub fn active_jobs(&self) -> String {
+        format!("{}:jobs:active", self.root)
+    }
+
+    // Returns the key telling that the legacy keys were moved to this layout.
+    pub fn migrated(&self) -> String {
+        format!("{}:migrated", self.root)
+    }
+}
+
+// Parts of the information about a domain stored next to its urls before the keys had a
+// prefix, as "{name}:{part}".
+pub const LEGACY_PARTS: [&str; 7] = [
+    "pages",
+    "blocked",
+    "graph",
+    "meta",
+    "robots",
+    "refreshing",
+    "snapshots",
+];
+
+// Parts of the checkpoint of the last crawl of a domain before the checkpoints belonged to
+// jobs, as "{name}:{part}". They cannot be told apart from each other's crawls, so they are
+// dropped instead of moved.
+pub const LEGACY_CHECKPOINT_PARTS: [&str; 5] = [
+    "crawl:depth",
+    "crawl:frontier",
+    "crawl:visited",
+    "crawl:graph",
+    "crawl:blocked",
+];
+
+// Legacy key of the set of the ids of the unfinished jobs.
+pub const LEGACY_ACTIVE_JOBS: &str = "jobs:active";
+
+// Checks if a key looks like the name of a domain, a host and an optional port, which was the
+// key of the set of its urls before the keys had a prefix. Keys that are not host names, such
+// as the keys of other applications, are left alone.
+pub fn is_legacy_domain(key: &str) -> bool {
+    let host = match key.rsplit_once(':') {
+        Some((host, port)) if !port.is_empty() && port.chars().all(|c| c.is_ascii_digit()) => host,
+        _ => key,
+    };
+    if host.starts_with('[') && host.ends_with(']') {
+        return host.len() > 2;
+    }
+    let valid = |c: char| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '.' || c == '-';
+    (host.contains('.') || host == "localhost")
+        && host.chars().all(valid)
+        && !host.starts_with('.')
+        && !host.ends_with('.')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keys() {
+        let keys = Keys::new(DEFAULT_PREFIX);
+        assert_eq!(
+            keys.domain("docs.rs", "urls"),
+            "crawler:v1:domain:docs.rs:urls"
+        );
+        assert_eq!(
+            keys.snapshot("127.0.0.1:8080", 1500000000),
+            "crawler:v1:domain:127.0.0.1:8080:snapshot:1500000000"
+        );
+        assert_eq!(keys.job("1f-2"), "crawler:v1:job:1f-2");
+        assert_eq!(
+            keys.checkpoint("1f-2", "depth"),
+            "crawler:v1:job:1f-2:crawl:depth"
+        );
+        assert_eq!(Keys::new("staging").active_jobs(), "staging:v1:jobs:active");
+        assert_eq!(Keys::new("staging").migrated(), "staging:v1:migrated");
+
+        assert!(is_legacy_domain("docs.rs"));
+        assert!(is_legacy_domain("127.0.0.1:8080"));
+        assert!(is_legacy_domain("localhost:3000"));
+        assert!(is_legacy_domain("[::1]:3000"));
+        assert!(!is_legacy_domain("docs.rs:pages"));
+        assert!(!is_legacy_domain("jobs:active"));
+        assert!(!is_legacy_domain("users"));
+        assert!(!is_legacy_domain("crawler:v1:domain:docs.rs:urls"));
+    }
+}
@@ -1,30 +1,61 @@
+use std::env;
 use std::net::SocketAddr;
 use std::process;
 use std::str::FromStr;
 
 type Result<T> = std::result::Result<T, error::CrawlError>;
 
+pub mod canonical;
+pub mod cli;
 pub mod crawler;
 pub mod database;
 pub mod domain;
 pub mod env_vars;
 pub mod error;
+pub mod export;
+pub mod jobs;
 pub mod json;
+pub mod keys;
+pub mod memory;
+pub mod metrics;
+pub mod options;
 pub mod parsing;
+pub mod politeness;
+pub mod robots;
+pub mod rules;
 pub mod server;
+pub mod sitemap;
+pub mod sqlite;
+pub mod storage;
 
 
-// Launches the server.
+// Launches the server, or runs the command given on the command line.
 fn main() {
-    match env_vars::set_env() {
-        Ok((host_address, port)) => {
-            let binding_address = host_address + ":" + &port;
-            let addr = SocketAddr::from_str(binding_address.as_str()).unwrap();
-            server::start_server(addr);
-        }
-        Err(e) => {
-            println!("{}", e);
-            process::exit(1);
-        }
+    let args: Vec<String> = env::args().skip(1).collect();
+    let (host_address, port) = env_vars::set_env().unwrap_or_else(|e| {
+        println!("{}", e);
+        process::exit(1);
+    });
+    let storage = storage::open().unwrap_or_else(|e| {
+        println!("storage not opened: {}", e);
+        process::exit(1);
+    });
+    if !args.is_empty() {
+        process::exit(cli::run(&args, &storage));
     }
+    match storage.migrate() {
+        Ok(0) => (),
+        Ok(migrated) => println!("{} domains and jobs moved to the new keys", migrated),
+        Err(e) => println!("keys not migrated: {}", e),
+    }
+    let jobs = jobs::JobQueue::from_env(storage.clone()).unwrap_or_else(|e| {
+        println!("job workers not started: {}", e);
+        process::exit(1);
+    });
+    if let Err(e) = jobs.recover_jobs() {
+        println!("interrupted jobs not recovered: {}", e);
+    }
+    let binding_address = host_address + ":" + &port;
+    let addr = SocketAddr::from_str(binding_address.as_str()).unwrap();
+    server::start_server(addr, storage, jobs);
 }
@@ -0,0 +1,305 @@
+use crate::crawler::Checkpoint;
+use crate::json::{EdgeJson, JobJson, JobState, PageJson, SnapshotPageJson};
+use crate::options::CrawlOptions;
+use crate::robots::Robots;
+use crate::rules::UrlRules;
+use crate::storage::{
+    bare_page, snapshots_kept, to_json, Storage, JOB_TTL, REFRESH_LOCK_TTL, ROBOTS_TTL,
+};
+use crate::Result;
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+// In-memory storage backend, for the tests and for the deployments that do not need to keep
+// their crawls when they stop.
+pub struct MemoryStorage {
+    data: Mutex<Data>,
+}
+
+// Everything stored, by domain name or job id. Values with an Instant expire at that time.
+#[derive(Default)]
+struct Data {
+    urls: HashMap<String, HashSet<String>>,
+    pages: HashMap<String, HashMap<String, PageJson>>,
+    blocked: HashMap<String, HashSet<String>>,
+    graphs: HashMap<String, HashMap<String, Vec<EdgeJson>>>,
+    rules: HashMap<String, UrlRules>,
+    options: HashMap<String, CrawlOptions>,
+    crawled_at: HashMap<String, u64>,
+    refreshing: HashMap<String, Instant>,
+    robots: HashMap<String, (Robots, Instant)>,
+    snapshots: HashMap<String, BTreeMap<u64, HashMap<String, SnapshotPageJson>>>,
+    checkpoints: HashMap<String, Checkpoint>,
+    // Jobs are kept serialized, like in the other backends.
+    jobs: HashMap<String, (String, Option<Instant>)>,
+}
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        MemoryStorage {
+            data: Mutex::new(Data::default()),
+        }
+    }
+
+    // Locks the data, even if a thread panicked while holding it.
+    fn data(&self) -> MutexGuard<'_, Data> {
+        self.data
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl Default for MemoryStorage {
+    fn default() -> Self {
+        MemoryStorage::new()
+    }
+}
+
+// Returns the time a value stored now for that many seconds expires.
+fn expires_in(secs: usize) -> Instant {
+    Instant::now() + Duration::from_secs(secs as u64)
+}
+
+impl Storage for MemoryStorage {
+    fn get_set(&self, domain_name: &str) -> Option<HashSet<String>> {
+        self.data().urls.get(domain_name).cloned()
+    }
+
+    fn get_len<'a>(&self, domain_name: &'a str) -> Option<(&'a str, usize)> {
+        self.data()
+            .urls
+            .get(domain_name)
+            .map(|urls| (domain_name, urls.len()))
+    }
+
+    fn set(&self, domain_name: &str, domain_set: HashSet<String>) -> Result<()> {
+        let mut data = self.data();
+        if domain_set.is_empty() {
+            data.urls.remove(domain_name);
+        } else {
+            data.urls.insert(domain_name.to_owned(), domain_set);
+        }
+        Ok(())
+    }
+
+    fn get_pages(&self, domain_name: &str) -> Option<Vec<PageJson>> {
+        let data = self.data();
+        let urls = data.urls.get(domain_name)?;
+        let stored = data.pages.get(domain_name);
+        Some(
+            urls.iter()
+                .map(|url| {
+                    stored
+                        .and_then(|pages| pages.get(url))
+                        .cloned()
+                        .unwrap_or_else(|| bare_page(url.clone()))
+                })
+                .collect(),
+        )
+    }
+
+    fn set_pages(&self, domain_name: &str, pages: &[PageJson]) -> Result<()> {
+        if pages.is_empty() {
+            return Ok(());
+        }
+        let mut data = self.data();
+        data.urls.insert(
+            domain_name.to_owned(),
+            pages.iter().map(|page| page.url.clone()).collect(),
+        );
+        data.pages.insert(
+            domain_name.to_owned(),
+            pages
+                .iter()
+                .map(|page| (page.url.clone(), page.clone()))
+                .collect(),
+        );
+        Ok(())
+    }
+
+    fn get_snapshots(&self, domain_name: &str) -> Vec<u64> {
+        self.data()
+            .snapshots
+            .get(domain_name)
+            .map(|snapshots| snapshots.keys().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    fn get_snapshot(
+        &self,
+        domain_name: &str,
+        crawled_at: u64,
+    ) -> Option<HashMap<String, SnapshotPageJson>> {
+        self.data()
+            .snapshots
+            .get(domain_name)?
+            .get(&crawled_at)
+            .cloned()
+    }
+
+    fn add_snapshot(&self, domain_name: &str, crawled_at: u64, pages: &[PageJson]) -> Result<()> {
+        let mut data = self.data();
+        let snapshots = data.snapshots.entry(domain_name.to_owned()).or_default();
+        snapshots.insert(
+            crawled_at,
+            pages
+                .iter()
+                .map(|page| (page.url.clone(), SnapshotPageJson::new(page)))
+                .collect(),
+        );
+        while snapshots.len() > snapshots_kept() {
+            let oldest = *snapshots.keys().next().unwrap();
+            snapshots.remove(&oldest);
+        }
+        Ok(())
+    }
+
+    fn get_blocked(&self, domain_name: &str) -> HashSet<String> {
+        self.data()
+            .blocked
+            .get(domain_name)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    fn set_blocked(&self, domain_name: &str, blocked_set: HashSet<String>) -> Result<()> {
+        self.data()
+            .blocked
+            .insert(domain_name.to_owned(), blocked_set);
+        Ok(())
+    }
+
+    fn get_graph(&self, domain_name: &str) -> Option<HashMap<String, Vec<EdgeJson>>> {
+        self.data()
+            .graphs
+            .get(domain_name)
+            .filter(|graph| !graph.is_empty())
+            .cloned()
+    }
+
+    fn set_graph(&self, domain_name: &str, graph: &HashMap<String, Vec<EdgeJson>>) -> Result<()> {
+        self.data()
+            .graphs
+            .insert(domain_name.to_owned(), graph.clone());
+        Ok(())
+    }
+
+    fn get_rules(&self, domain_name: &str) -> Option<UrlRules> {
+        self.data().rules.get(domain_name).cloned()
+    }
+
+    fn set_rules(&self, domain_name: &str, rules: &UrlRules) -> Result<()> {
+        self.data()
+            .rules
+            .insert(domain_name.to_owned(), rules.clone());
+        Ok(())
+    }
+
+    fn get_options(&self, domain_name: &str) -> Option<CrawlOptions> {
+        self.data().options.get(domain_name).cloned()
+    }
+
+    fn set_options(&self, domain_name: &str, options: &CrawlOptions) -> Result<()> {
+        self.data()
+            .options
+            .insert(domain_name.to_owned(), options.clone());
+        Ok(())
+    }
+
+    fn get_crawled_at(&self, domain_name: &str) -> Option<u64> {
+        self.data().crawled_at.get(domain_name).cloned()
+    }
+
+    fn set_crawled_at(&self, domain_name: &str, crawled_at: u64) -> Result<()> {
+        self.data()
+            .crawled_at
+            .insert(domain_name.to_owned(), crawled_at);
+        Ok(())
+    }
+
+    fn lock_refresh(&self, domain_name: &str) -> Result<bool> {
+        let mut data = self.data();
+        match data.refreshing.get(domain_name) {
+            Some(expires) if *expires > Instant::now() => Ok(false),
+            _ => {
+                data.refreshing
+                    .insert(domain_name.to_owned(), expires_in(REFRESH_LOCK_TTL));
+                Ok(true)
+            }
+        }
+    }
+
+    fn unlock_refresh(&self, domain_name: &str) -> Result<()> {
+        self.data().refreshing.remove(domain_name);
+        Ok(())
+    }
+
+    fn get_robots(&self, domain_name: &str, user_agent: &str) -> Option<Robots> {
+        self.data()
+            .robots
+            .get(domain_name)
+            .filter(|(robots, expires)| {
+                *expires > Instant::now() && robots.user_agent == user_agent
+            })
+            .map(|(robots, _)| robots.clone())
+    }
+
+    fn set_robots(&self, domain_name: &str, robots: &Robots) -> Result<()> {
+        self.data().robots.insert(
+            domain_name.to_owned(),
+            (robots.clone(), expires_in(ROBOTS_TTL)),
+        );
+        Ok(())
+    }
+
+    fn get_checkpoint(&self, job_id: &str) -> Option<Checkpoint> {
+        self.data().checkpoints.get(job_id).cloned()
+    }
+
+    fn set_checkpoint(&self, job_id: &str, checkpoint: &Checkpoint) -> Result<()> {
+        self.data()
+            .checkpoints
+            .insert(job_id.to_owned(), checkpoint.clone());
+        Ok(())
+    }
+
+    fn delete_checkpoint(&self, job_id: &str) -> Result<()> {
+        self.data().checkpoints.remove(job_id);
+        Ok(())
+    }
+
+    fn get_job(&self, id: &str) -> Option<JobJson> {
+        self.data()
+            .jobs
+            .get(id)
+            .filter(|(_, expires)| expires.iter().all(|expires| *expires > Instant::now()))
+            .and_then(|(content, _)| serde_json::from_str(content).ok())
+    }
+
+    fn set_job(&self, job: &JobJson) -> Result<()> {
+        let content = to_json(job)?;
+        let expires = match job.state {
+            JobState::Queued | JobState::Running => None,
+            JobState::Done | JobState::Failed => Some(expires_in(JOB_TTL)),
+        };
+        self.data().jobs.insert(job.id.clone(), (content, expires));
+        Ok(())
+    }
+
+    fn delete_job(&self, id: &str) -> Result<()> {
+        self.data().jobs.remove(id);
+        Ok(())
+    }
+
+    fn get_active_jobs(&self) -> Result<HashSet<String>> {
+        Ok(self
+            .data()
+            .jobs
+            .iter()
+            .filter(|(_, (_, expires))| expires.is_none())
+            .map(|(id, _)| id.clone())
+            .collect())
+    }
+}
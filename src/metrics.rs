@@ -0,0 +1,151 @@
+use crate::json::{EdgeJson, MetricsJson, PageJson};
+
+use std::collections::{HashMap, HashSet};
+
+// Probability of following a link rather than jumping to a random page.
+const DAMPING: f64 = 0.85;
+const MAX_ITERATIONS: usize = 100;
+const TOLERANCE: f64 = 1e-9;
+
+// Computes the metrics of each crawled page from the links between them: internal PageRank,
+// the number of pages linking to it and of pages it links to, and the page it was
+// discovered from. Links to urls that were not crawled and links of a page to itself
+// are left out.
+pub fn page_metrics(
+    pages: &[PageJson],
+    graph: &HashMap<String, Vec<EdgeJson>>,
+) -> HashMap<String, MetricsJson> {
+    let index: HashMap<&str, usize> = pages
+        .iter()
+        .enumerate()
+        .map(|(position, page)| (page.url.as_str(), position))
+        .collect();
+
+    // Keeps one link per pair of pages.
+    let mut outlinks: Vec<HashSet<usize>> = vec![HashSet::new(); pages.len()];
+    let mut inlinks: Vec<usize> = vec![0; pages.len()];
+    for (source, edges) in graph {
+        let source = match index.get(source.as_str()) {
+            Some(source) => *source,
+            None => continue,
+        };
+        for edge in edges {
+            if let Some(&target) = index.get(edge.target.as_str()) {
+                if target != source && outlinks[source].insert(target) {
+                    inlinks[target] += 1;
+                }
+            }
+        }
+    }
+
+    let ranks = page_rank(&outlinks);
+    pages
+        .iter()
+        .enumerate()
+        .map(|(position, page)| {
+            let metrics = MetricsJson {
+                page_rank: ranks[position],
+                inlinks: inlinks[position],
+                outlinks: outlinks[position].len(),
+                discovered_from: page.referrers.first().cloned(),
+            };
+            (page.url.clone(), metrics)
+        })
+        .collect()
+}
+
+// Iterates the PageRank of the nodes of a graph until it converges. The rank of the pages
+// without links is shared between every page.
+fn page_rank(outlinks: &[HashSet<usize>]) -> Vec<f64> {
+    let size = outlinks.len();
+    if size == 0 {
+        return vec![];
+    }
+    let mut ranks = vec![1.0 / size as f64; size];
+    for _ in 0..MAX_ITERATIONS {
+        let dangling: f64 = outlinks
+            .iter()
+            .zip(&ranks)
+            .filter(|(targets, _)| targets.is_empty())
+            .map(|(_, rank)| rank)
+            .sum();
+        let base = (1.0 - DAMPING + DAMPING * dangling) / size as f64;
+        let mut next = vec![base; size];
+        for (source, targets) in outlinks.iter().enumerate() {
+            for &target in targets {
+                next[target] += DAMPING * ranks[source] / targets.len() as f64;
+            }
+        }
+        let change: f64 = next.iter().zip(&ranks).map(|(a, b)| (a - b).abs()).sum();
+        ranks = next;
+        if change < TOLERANCE {
+            break;
+        }
+    }
+    ranks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsing::LinkSource;
+
+    fn page(url: &str, referrer: Option<&str>) -> PageJson {
+        PageJson {
+            url: url.to_owned(),
+            depth: None,
+            sources: vec![],
+            referrers: referrer.into_iter().map(ToOwned::to_owned).collect(),
+            sitemap: None,
+            fetch: None,
+            metrics: None,
+            change: None,
+        }
+    }
+
+    fn edge(source: &str, target: &str) -> EdgeJson {
+        EdgeJson {
+            source: source.to_owned(),
+            target: target.to_owned(),
+            text: None,
+            rel: None,
+            element: LinkSource::A,
+        }
+    }
+
+    #[test]
+    fn test_page_metrics() {
+        let pages = vec![
+            page("/", None),
+            page("/a", Some("/")),
+            page("/b", Some("/")),
+            page("/orphan", None),
+        ];
+        let mut graph = HashMap::new();
+        graph.insert(
+            String::from("/"),
+            vec![
+                edge("/", "/a"),
+                edge("/", "/a"),
+                edge("/", "/b"),
+                edge("/", "/"),
+            ],
+        );
+        graph.insert(
+            String::from("/a"),
+            vec![edge("/a", "/"), edge("/a", "https://other.com/")],
+        );
+        graph.insert(String::from("/b"), vec![edge("/b", "/a")]);
+
+        let metrics = page_metrics(&pages, &graph);
+        assert_eq!(metrics["/"].outlinks, 2);
+        assert_eq!(metrics["/a"].inlinks, 2);
+        assert_eq!(metrics["/a"].outlinks, 1);
+        assert_eq!(metrics["/orphan"].inlinks, 0);
+        assert_eq!(metrics["/b"].discovered_from, Some(String::from("/")));
+        assert!(metrics["/a"].page_rank > metrics["/b"].page_rank);
+        assert!(metrics["/b"].page_rank > metrics["/orphan"].page_rank);
+        let total: f64 = metrics.values().map(|metrics| metrics.page_rank).sum();
+        assert!((total - 1.0).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,369 @@
+use crate::canonical::CanonicalOptions;
+use crate::domain::ScopeOptions;
+use crate::error::{CrawlError, ErrorType};
+use crate::json::CrawlRequestJson;
+use crate::parsing::LinkSource;
+use crate::politeness::PolitenessConfig;
+use crate::rules::UrlRules;
+use crate::Result;
+
+use serde::{Deserialize, Serialize};
+
+use url::form_urlencoded;
+
+use std::env;
+use std::str::FromStr;
+use std::time::Duration;
+
+// Settings of a single crawl. Defaults come from the environment variables and can be
+// overridden by the query parameters of the crawl request.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct CrawlOptions {
+    pub limit: usize,
+    pub max_depth: Option<usize>,
+    pub user_agent: String,
+    pub politeness: PolitenessConfig,
+    pub canonical: CanonicalOptions,
+    #[serde(default = "LinkSource::all")]
+    pub link_sources: Vec<LinkSource>,
+    #[serde(default = "enabled")]
+    pub sitemaps: bool,
+    #[serde(default)]
+    pub sitemap_only: bool,
+    #[serde(default)]
+    pub rules: UrlRules,
+    #[serde(default)]
+    pub scope: ScopeOptions,
+    #[serde(default)]
+    pub force_refresh: bool,
+    #[serde(default)]
+    pub ttl_secs: Option<u64>,
+    #[serde(default)]
+    pub stale_while_revalidate: bool,
+}
+
+impl CrawlOptions {
+    // Reads the default settings from the environment variables.
+    pub fn from_env() -> Result<Self> {
+        let default = CrawlOptions::default();
+        let default_politeness = default.politeness;
+        let default_canonical = default.canonical;
+        Ok(CrawlOptions {
+            limit: env::var("URL_LIST_MAX_SIZE")?
+                .parse()
+                .unwrap_or(default.limit),
+            max_depth: env::var("MAX_DEPTH")
+                .ok()
+                .and_then(|value| value.parse().ok()),
+            user_agent: env::var("USER_AGENT").unwrap_or(default.user_agent),
+            politeness: PolitenessConfig {
+                min_delay: env_millis("MIN_DELAY_MS").unwrap_or(default_politeness.min_delay),
+                max_delay: env_millis("MAX_DELAY_MS").unwrap_or(default_politeness.max_delay),
+                max_in_flight: env::var("MAX_IN_FLIGHT")
+                    .ok()
+                    .and_then(|value| value.parse().ok())
+                    .unwrap_or(default_politeness.max_in_flight),
+            },
+            canonical: CanonicalOptions {
+                trailing_slash: env::var("TRAILING_SLASH")
+                    .ok()
+                    .and_then(|value| value.parse().ok())
+                    .unwrap_or(default_canonical.trailing_slash),
+                sort_query: env::var("SORT_QUERY")
+                    .ok()
+                    .and_then(|value| value.parse().ok())
+                    .unwrap_or(default_canonical.sort_query),
+                ignored_params: env::var("IGNORED_PARAMS")
+                    .map(|value| parse_list(&value))
+                    .unwrap_or(default_canonical.ignored_params),
+            },
+            link_sources: match env::var("LINK_SOURCES") {
+                Ok(value) => parse_sources(&value)?,
+                Err(_) => LinkSource::all(),
+            },
+            sitemaps: env::var("SITEMAPS")
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(true),
+            sitemap_only: false,
+            rules: UrlRules::default(),
+            scope: ScopeOptions {
+                mode: env::var("SCOPE")
+                    .ok()
+                    .and_then(|value| value.parse().ok())
+                    .unwrap_or_default(),
+                aliases: env::var("HOST_ALIASES")
+                    .map(|value| parse_list(&value))
+                    .unwrap_or_default(),
+            },
+            force_refresh: false,
+            ttl_secs: env::var("CRAWL_TTL_SECS")
+                .ok()
+                .and_then(|value| value.parse().ok()),
+            stale_while_revalidate: env::var("STALE_WHILE_REVALIDATE")
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(false),
+        })
+    }
+
+    // Reads the default settings, then applies the parameters of a request query.
+    pub fn from_query(query: Option<&str>) -> Result<Self> {
+        let mut options = CrawlOptions::from_env()?;
+        let pairs = query
+            .map(|query| form_urlencoded::parse(query.as_bytes()).into_owned())
+            .into_iter()
+            .flatten();
+        for (key, value) in pairs {
+            match key.as_str() {
+                "max_depth" => options.max_depth = Some(parse_option(&value)?),
+                "min_delay_ms" => {
+                    options.politeness.min_delay = Duration::from_millis(parse_option(&value)?)
+                }
+                "max_delay_ms" => {
+                    options.politeness.max_delay = Duration::from_millis(parse_option(&value)?)
+                }
+                "max_in_flight" => options.politeness.max_in_flight = parse_option(&value)?,
+                "trailing_slash" => options.canonical.trailing_slash = parse_option(&value)?,
+                "sort_query" => options.canonical.sort_query = parse_option(&value)?,
+                "ignored_params" => options.canonical.ignored_params = parse_list(&value),
+                "link_sources" => options.link_sources = parse_sources(&value)?,
+                "sitemaps" => options.sitemaps = parse_option(&value)?,
+                "sitemap_only" => options.sitemap_only = parse_option(&value)?,
+                "include" => options.rules.include.push(value),
+                "exclude" => options.rules.exclude.push(value),
+                "scope" => options.scope.mode = parse_option(&value)?,
+                "aliases" => options.scope.aliases = parse_list(&value),
+                "force_refresh" => options.force_refresh = parse_option(&value)?,
+                "ttl_secs" => options.ttl_secs = Some(parse_option(&value)?),
+                "stale_while_revalidate" => options.stale_while_revalidate = parse_option(&value)?,
+                _ => return Err(CrawlError::new(ErrorType::InvalidOption)),
+            }
+        }
+        Ok(options)
+    }
+
+    // Reads the settings of a crawl request: the defaults, then the query parameters, then
+    // the fields of its JSON body. Checks that the settings can be used together.
+    pub fn from_request(query: Option<&str>, request: CrawlRequestJson) -> Result<Self> {
+        let mut options = CrawlOptions::from_query(query)?;
+        let politeness = &mut options.politeness;
+        let canonical = &mut options.canonical;
+        options.limit = request.max_pages.unwrap_or(options.limit);
+        options.max_depth = request.max_depth.or(options.max_depth);
+        options.user_agent = request.user_agent.unwrap_or(options.user_agent);
+        options.scope.mode = request.scope.unwrap_or(options.scope.mode);
+        options.scope.aliases = request.aliases.unwrap_or(options.scope.aliases);
+        options.rules.include.extend(request.include);
+        options.rules.exclude.extend(request.exclude);
+        options.link_sources = request.link_sources.unwrap_or(options.link_sources);
+        options.sitemaps = request.sitemaps.unwrap_or(options.sitemaps);
+        options.sitemap_only = request.sitemap_only.unwrap_or(options.sitemap_only);
+        if let Some(delay) = request.min_delay_ms {
+            politeness.min_delay = Duration::from_millis(delay);
+        }
+        if let Some(delay) = request.max_delay_ms {
+            politeness.max_delay = Duration::from_millis(delay);
+        }
+        politeness.max_in_flight = request.max_in_flight.unwrap_or(politeness.max_in_flight);
+        canonical.trailing_slash = request.trailing_slash.unwrap_or(canonical.trailing_slash);
+        canonical.sort_query = request.sort_query.unwrap_or(canonical.sort_query);
+        if let Some(params) = request.ignored_params {
+            canonical.ignored_params = params;
+        }
+        options.force_refresh = request.force_refresh.unwrap_or(options.force_refresh);
+        options.ttl_secs = request.ttl_secs.or(options.ttl_secs);
+        options.stale_while_revalidate = request
+            .stale_while_revalidate
+            .unwrap_or(options.stale_while_revalidate);
+        options.validate()?;
+        Ok(options)
+    }
+
+    // Checks the settings, and explains the first problem found.
+    pub fn validate(&self) -> Result<()> {
+        let problem = if self.limit == 0 {
+            Some("max_pages should be at least 1")
+        } else if self.politeness.max_in_flight == 0 {
+            Some("max_in_flight should be at least 1")
+        } else if self.politeness.min_delay > self.politeness.max_delay {
+            Some("min_delay_ms should not be greater than max_delay_ms")
+        } else if self.user_agent.trim().is_empty() {
+            Some("user_agent should not be empty")
+        } else if self.link_sources.is_empty() {
+            Some("link_sources should not be empty")
+        } else {
+            None
+        };
+        match problem {
+            Some(message) => Err(CrawlError::new(ErrorType::InvalidRequest(
+                message.to_owned(),
+            ))),
+            None => self.rules.compile().map(|_| ()),
+        }
+    }
+}
+
+// The settings used when no environment variable changes them.
+impl Default for CrawlOptions {
+    fn default() -> Self {
+        CrawlOptions {
+            limit: 50,
+            max_depth: None,
+            user_agent: String::from("rust-crawler"),
+            politeness: PolitenessConfig::default(),
+            canonical: CanonicalOptions::default(),
+            link_sources: LinkSource::all(),
+            sitemaps: true,
+            sitemap_only: false,
+            rules: UrlRules::default(),
+            scope: ScopeOptions::default(),
+            force_refresh: false,
+            ttl_secs: None,
+            stale_while_revalidate: false,
+        }
+    }
+}
+
+// Serializes durations as a number of milliseconds.
+pub mod millis {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::time::Duration;
+
+    pub fn serialize<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u64(duration.as_millis() as u64)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        u64::deserialize(deserializer).map(Duration::from_millis)
+    }
+}
+
+fn enabled() -> bool {
+    true
+}
+
+fn parse_option<T: FromStr>(value: &str) -> Result<T> {
+    value
+        .parse()
+        .map_err(|_| CrawlError::new(ErrorType::InvalidOption))
+}
+
+// Splits a comma separated list of values.
+fn parse_list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(ToOwned::to_owned)
+        .collect()
+}
+
+// Reads a comma separated list of the kinds of elements to extract links from.
+fn parse_sources(value: &str) -> Result<Vec<LinkSource>> {
+    parse_list(value)
+        .iter()
+        .map(|source| parse_option(source))
+        .collect()
+}
+
+fn env_millis(name: &str) -> Option<Duration> {
+    env::var(name)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .map(Duration::from_millis)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::canonical::TrailingSlash;
+    use crate::domain::ScopeMode;
+
+    #[test]
+    fn test_query_options() {
+        env::set_var("URL_LIST_MAX_SIZE", "50");
+        let options =
+            CrawlOptions::from_query(Some("min_delay_ms=250&max_in_flight=2&max_depth=3")).unwrap();
+        assert_eq!(options.politeness.min_delay, Duration::from_millis(250));
+        assert_eq!(options.politeness.max_in_flight, 2);
+        assert_eq!(options.max_depth, Some(3));
+
+        let options =
+            CrawlOptions::from_query(Some("trailing_slash=strip&ignored_params=utm_*,%20sid"))
+                .unwrap();
+        assert_eq!(options.canonical.trailing_slash, TrailingSlash::Strip);
+        assert_eq!(options.canonical.ignored_params, vec!["utm_*", "sid"]);
+
+        let options =
+            CrawlOptions::from_query(Some("include=%2Fdocs%2F*&exclude=*.pdf&exclude=%2Fsearch*"))
+                .unwrap();
+        assert_eq!(options.rules.include, vec!["/docs/*"]);
+        assert_eq!(options.rules.exclude, vec!["*.pdf", "/search*"]);
+
+        let options = CrawlOptions::from_query(Some("scope=subdomains&aliases=docs.rs")).unwrap();
+        assert_eq!(options.scope.mode, ScopeMode::Subdomains);
+        assert_eq!(options.scope.aliases, vec!["docs.rs"]);
+
+        let options = CrawlOptions::from_query(Some("link_sources=a,iframe")).unwrap();
+        assert_eq!(
+            options.link_sources,
+            vec![LinkSource::A, LinkSource::Iframe]
+        );
+
+        assert_eq!(
+            CrawlOptions::from_query(Some("max_in_flight=many")),
+            Err(CrawlError::new(ErrorType::InvalidOption))
+        );
+        assert_eq!(
+            CrawlOptions::from_query(Some("link_sources=a,img")),
+            Err(CrawlError::new(ErrorType::InvalidOption))
+        );
+        assert_eq!(
+            CrawlOptions::from_query(Some("unknown=1")),
+            Err(CrawlError::new(ErrorType::InvalidOption))
+        );
+    }
+
+    #[test]
+    fn test_request_options() {
+        env::set_var("URL_LIST_MAX_SIZE", "50");
+        let request = CrawlRequestJson {
+            url: String::from("https://docs.rs"),
+            max_pages: Some(10),
+            include: vec![String::from("/docs/*")],
+            max_in_flight: Some(1),
+            force_refresh: Some(true),
+            ..Default::default()
+        };
+        let options =
+            CrawlOptions::from_request(Some("max_in_flight=3&max_depth=2"), request).unwrap();
+        assert_eq!(options.limit, 10);
+        assert_eq!(options.max_depth, Some(2));
+        assert_eq!(options.politeness.max_in_flight, 1);
+        assert_eq!(options.rules.include, vec!["/docs/*"]);
+        assert!(options.force_refresh);
+
+        let invalid = |request: CrawlRequestJson| CrawlOptions::from_request(None, request);
+        assert_eq!(
+            invalid(CrawlRequestJson {
+                max_pages: Some(0),
+                ..Default::default()
+            }),
+            Err(CrawlError::new(ErrorType::InvalidRequest(String::from(
+                "max_pages should be at least 1"
+            ))))
+        );
+        assert_eq!(
+            invalid(CrawlRequestJson {
+                min_delay_ms: Some(2000),
+                max_delay_ms: Some(1000),
+                ..Default::default()
+            }),
+            Err(CrawlError::new(ErrorType::InvalidRequest(String::from(
+                "min_delay_ms should not be greater than max_delay_ms"
+            ))))
+        );
+    }
+}
@@ -1,13 +1,21 @@
-use crate::domain::Domain;
+use crate::canonical::{canonicalize, CanonicalOptions};
+use crate::domain::host_key;
 use crate::error::{CrawlError, ErrorType};
+use crate::export::ExportFormat;
+use crate::json::CrawlRequestJson;
 use crate::Result;
 
 use hyper::Uri;
 
-use scraper::{Html, Selector};
+use scraper::node::Element;
+use scraper::{ElementRef, Html, Selector};
+
+use serde::{Deserialize, Serialize};
 
 use url::{form_urlencoded, Url};
 
+use std::str::FromStr;
+
 // Returns a valid domain as a string, parsing it from a GET request query parameter.
 pub fn parse_domain(uri: &Uri) -> Result<String> {
 
@@ -21,39 +29,229 @@ pub fn parse_domain(uri: &Uri) -> Result<String> {
         })
         .ok_or_else(|| CrawlError::new(ErrorType::MissingParameter))?;
 
-    // Checks that it is a well formatted domain and returns it as a String.
-    Url::parse(&domain_parameter)?
-        .domain()
-        .ok_or_else(|| CrawlError::new(ErrorType::WrongDomain))
-        .map(ToOwned::to_owned)
+    // Checks that it is a well formatted domain and returns its host and port as a String.
+    host_key(&Url::parse(&domain_parameter)?).ok_or_else(|| CrawlError::new(ErrorType::WrongDomain))
+
+}
+
+// Kinds of elements the links of a page can be extracted from.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum LinkSource {
+    A,
+    Area,
+    Iframe,
+    Frame,
+    Link,
+    Form,
+    Meta,
+}
+
+impl LinkSource {
+    // Returns every kind of element, in the order their links are extracted.
+    pub fn all() -> Vec<LinkSource> {
+        vec![
+            LinkSource::A,
+            LinkSource::Area,
+            LinkSource::Iframe,
+            LinkSource::Frame,
+            LinkSource::Link,
+            LinkSource::Form,
+            LinkSource::Meta,
+        ]
+    }
+
+    // Returns the css selector of the elements and the attribute containing their link.
+    fn selector(self) -> (&'static str, &'static str) {
+        match self {
+            LinkSource::A => ("a[href]", "href"),
+            LinkSource::Area => ("area[href]", "href"),
+            LinkSource::Iframe => ("iframe[src]", "src"),
+            LinkSource::Frame => ("frame[src]", "src"),
+            LinkSource::Link => ("link[href][rel]", "href"),
+            LinkSource::Form => ("form[action]", "action"),
+            LinkSource::Meta => ("meta[http-equiv][content]", "content"),
+        }
+    }
+
+    // Reads the link of an element, if it leads to another page.
+    fn extract(self, element: &Element) -> Option<&str> {
+        let value = element.attr(self.selector().1)?;
+        match self {
+            // Only follows the links to the next and previous pages and to the alternate
+            // versions of the page, not the stylesheets or the icons.
+            LinkSource::Link => element
+                .attr("rel")?
+                .split_whitespace()
+                .any(|rel| {
+                    ["next", "prev", "alternate"]
+                        .iter()
+                        .any(|kind| rel.eq_ignore_ascii_case(kind))
+                })
+                .then_some(value),
+            // Posting a form is not navigating.
+            LinkSource::Form => element
+                .attr("method")
+                .map(|method| method.trim().eq_ignore_ascii_case("get"))
+                .unwrap_or(true)
+                .then_some(value),
+            // Reads the url of a "5; url=/next" refresh.
+            LinkSource::Meta if element.attr("http-equiv")?.eq_ignore_ascii_case("refresh") => {
+                let (_, target) = value.split_once(';')?;
+                let (name, url) = target.split_once('=')?;
+                name.trim()
+                    .eq_ignore_ascii_case("url")
+                    .then_some(url.trim().trim_matches(|c| c == '\'' || c == '"'))
+            }
+            LinkSource::Meta => None,
+            _ => Some(value),
+        }
+    }
+}
+
+impl FromStr for LinkSource {
+    type Err = ();
+
+    fn from_str(value: &str) -> std::result::Result<Self, Self::Err> {
+        match value {
+            "a" => Ok(LinkSource::A),
+            "area" => Ok(LinkSource::Area),
+            "iframe" => Ok(LinkSource::Iframe),
+            "frame" => Ok(LinkSource::Frame),
+            "link" => Ok(LinkSource::Link),
+            "form" => Ok(LinkSource::Form),
+            "meta" => Ok(LinkSource::Meta),
+            _ => Err(()),
+        }
+    }
+}
 
+// A link found in a page, with the kind of element it was found in, its text and its
+// rel attribute. The text of the elements without content is their alt or title attribute.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Link {
+    pub url: Url,
+    pub source: LinkSource,
+    pub text: Option<String>,
+    pub rel: Option<String>,
 }
 
-// Returns a vector of reqwest::Url objects containing every hyperlink found in some HTML string.
-pub fn parse_html_links(domain: &Domain, html: String) -> Vec<reqwest::Url> {
+// Returns every link found in the HTML content of a page in the given kinds of elements,
+// in their canonical form. Relative links are resolved against the url of the page,
+// or against the address of its <base href> element if there is one.
+pub fn parse_html_links(
+    page_url: &Url,
+    html: String,
+    sources: &[LinkSource],
+    canonical: &CanonicalOptions,
+) -> Vec<Link> {
     // Parses the HTML.
     let dom = Html::parse_document(&html);
-    let link_selector = Selector::parse("a").unwrap();
+    let base_selector = Selector::parse("base[href]").unwrap();
     let mut links = vec![];
 
-    // Iterates through each hyperlink.
-    for element in dom.select(&link_selector) {
-        if let Some(href) = element.value().attr("href") {
-            // Checks if the link is relative, and completes it with the domain name if necessary
-            if href.starts_with('/') {
-                links.push(domain.create_url_from_path(href));
-            } else if let Ok(url) = Url::parse(href) {
-                links.push(url);
+    // Finds the base url of the relative links.
+    let base_url = dom
+        .select(&base_selector)
+        .next()
+        .and_then(|base| base.value().attr("href"))
+        .and_then(|href| page_url.join(href.trim()).ok())
+        .unwrap_or_else(|| page_url.clone());
+
+    // Iterates through each kind of element, and only keeps the links leading to web pages.
+    for &source in sources {
+        let selector = Selector::parse(source.selector().0).unwrap();
+        for element in dom.select(&selector) {
+            if let Some(href) = source.extract(element.value()) {
+                match base_url.join(href.trim()) {
+                    Ok(ref url) if url.scheme() == "http" || url.scheme() == "https" => {
+                        links.push(Link {
+                            url: canonicalize(url, canonical),
+                            source,
+                            text: link_text(element),
+                            rel: element.value().attr("rel").map(ToOwned::to_owned),
+                        })
+                    }
+                    _ => (),
+                }
             }
         }
     }
     links
 }
 
+// Reads the text of a link with its whitespace collapsed, or the alt or title attribute
+// of the elements without text.
+fn link_text(element: ElementRef) -> Option<String> {
+    let text = element
+        .text()
+        .flat_map(str::split_whitespace)
+        .collect::<Vec<_>>()
+        .join(" ");
+    if !text.is_empty() {
+        return Some(text);
+    }
+    ["alt", "title"]
+        .iter()
+        .filter_map(|name| element.value().attr(name))
+        .map(str::trim)
+        .find(|text| !text.is_empty())
+        .map(ToOwned::to_owned)
+}
+
+// Reads the body of a crawl request: either the url to crawl, or a JSON object with the url
+// and the settings of the crawl.
+pub fn parse_crawl_request(content: &str) -> Result<CrawlRequestJson> {
+    if !content.trim_start().starts_with('{') {
+        return Ok(CrawlRequestJson {
+            url: content.trim().to_owned(),
+            ..Default::default()
+        });
+    }
+    serde_json::from_str(content).map_err(|e| {
+        CrawlError::new(ErrorType::InvalidRequest(format!(
+            "Invalid request body: {}",
+            e
+        )))
+    })
+}
+
+// Reads the optional 'source' parameter of a GET request query, used to only list the urls
+// found in a kind of element.
+pub fn parse_source(uri: &Uri) -> Result<Option<LinkSource>> {
+    parse_parameter(uri, "source")
+}
+
+// Reads the optional 'format' parameter of a GET request query, JSON Graph by default.
+pub fn parse_format(uri: &Uri) -> Result<ExportFormat> {
+    parse_parameter(uri, "format").map(|format| format.unwrap_or(ExportFormat::JsonGraph))
+}
+
+// Reads the optional 'from' and 'to' parameters of a GET request query, the times of the
+// snapshots to compare.
+pub fn parse_snapshots(uri: &Uri) -> Result<(Option<u64>, Option<u64>)> {
+    Ok((parse_parameter(uri, "from")?, parse_parameter(uri, "to")?))
+}
+
+// Reads an optional parameter from a GET request query.
+fn parse_parameter<T: FromStr>(uri: &Uri, name: &str) -> Result<Option<T>> {
+    uri.query()
+        .and_then(|query| {
+            form_urlencoded::parse(query.as_bytes())
+                .into_owned()
+                .find(|pair| pair.0 == name)
+        })
+        .map(|(_, value)| {
+            value
+                .parse()
+                .map_err(|_| CrawlError::new(ErrorType::InvalidOption))
+        })
+        .transpose()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::str::FromStr;
 
     #[test]
     fn test_domain_parsing() {
@@ -62,6 +260,12 @@ mod tests {
         let missing_parameter_uri = Uri::from_str("https://www.webcrawler.com/nb-urls").unwrap();
 
         assert_eq!(parse_domain(&valid_uri_query), Ok(String::from("docs.rs")));
+        let ip_uri_query =
+            Uri::from_str("https://www.webcrawler.com/urls?domain=http://127.0.0.1:8080").unwrap();
+        assert_eq!(
+            parse_domain(&ip_uri_query),
+            Ok(String::from("127.0.0.1:8080"))
+        );
         assert_eq!(
             parse_domain(&missing_parameter_uri),
             Err(CrawlError::new(ErrorType::MissingParameter))
@@ -70,7 +274,7 @@ mod tests {
 
     #[test]
     fn test_html_parsing() {
-        let domain = Domain::new("https://docs.rs").unwrap();
+        let page_url = Url::parse("https://docs.rs").unwrap();
         let html = r##"
             <a href="#">
             <a href="/someotherdocumentation">
@@ -78,11 +282,143 @@ mod tests {
             <a href="https://docs.rs/hyper/0.12.32/hyper/">
             "##
         .to_owned();
-        let mut links = parse_html_links(&domain, html).into_iter();
+        let mut links = parse_html_links(&page_url, html, &[LinkSource::A], &Default::default())
+            .into_iter()
+            .map(|link| link.url);
         assert!(links.next().unwrap().has_host());
+        assert_eq!(
+            links.next().unwrap(),
+            Url::parse("https://docs.rs/someotherdocumentation").unwrap()
+        );
         assert_eq!(
             links.next().unwrap(),
             Url::parse("https://docs.rs/hyper/0.12.32/hyper/").unwrap()
         )
     }
+
+    #[test]
+    fn test_relative_links() {
+        let page_url = Url::parse("https://docs.rs/crate/docs/index.html?version=1").unwrap();
+        let links = |html: &str| -> Vec<String> {
+            parse_html_links(
+                &page_url,
+                html.to_owned(),
+                &[LinkSource::A],
+                &Default::default(),
+            )
+            .into_iter()
+            .map(|link| link.url.into_string())
+            .collect()
+        };
+
+        assert_eq!(
+            links(r#"<a href="page2.html">"#),
+            vec!["https://docs.rs/crate/docs/page2.html"]
+        );
+        assert_eq!(
+            links(r#"<a href="../about">"#),
+            vec!["https://docs.rs/crate/about"]
+        );
+        assert_eq!(
+            links(r#"<a href="?page=3">"#),
+            vec!["https://docs.rs/crate/docs/index.html?page=3"]
+        );
+        assert_eq!(
+            links(r#"<a href="/search?q=rust">"#),
+            vec!["https://docs.rs/search?q=rust"]
+        );
+        assert_eq!(
+            links(r#"<a href="//crates.io/crates/url">"#),
+            vec!["https://crates.io/crates/url"]
+        );
+        assert_eq!(
+            links(r#"<a href="mailto:contact@docs.rs"><a href="javascript:void(0)">"#),
+            Vec::<String>::new()
+        );
+        assert_eq!(
+            links(r#"<head><base href="/other/dir/"></head><a href="page.html"><a href="/root">"#),
+            vec![
+                "https://docs.rs/other/dir/page.html",
+                "https://docs.rs/root"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_link_sources() {
+        let page_url = Url::parse("https://docs.rs/page").unwrap();
+        let html = r#"
+            <head>
+                <link rel="stylesheet" href="/style.css">
+                <link rel="next" href="/page/2">
+                <link rel="alternate" hreflang="fr" href="/fr/page">
+                <meta http-equiv="Refresh" content="5; URL='/moved'">
+                <meta http-equiv="content-type" content="text/html; url=/nothing">
+            </head>
+            <map><area href="/area" alt=""></map>
+            <iframe src="/frame.html"></iframe>
+            <form action="/search"></form>
+            <form method="post" action="/login"></form>
+            "#;
+        let links = |sources: &[LinkSource]| -> Vec<(String, LinkSource)> {
+            parse_html_links(&page_url, html.to_owned(), sources, &Default::default())
+                .into_iter()
+                .map(|link| (link.url.path().to_owned(), link.source))
+                .collect()
+        };
+
+        assert_eq!(
+            links(&LinkSource::all()),
+            vec![
+                (String::from("/area"), LinkSource::Area),
+                (String::from("/frame.html"), LinkSource::Iframe),
+                (String::from("/page/2"), LinkSource::Link),
+                (String::from("/fr/page"), LinkSource::Link),
+                (String::from("/search"), LinkSource::Form),
+                (String::from("/moved"), LinkSource::Meta),
+            ]
+        );
+        assert_eq!(links(&[LinkSource::A, LinkSource::Frame]), vec![]);
+
+        let html = r#"<a href="/docs" rel="nofollow"> The <b>docs</b>
+            page </a><area href="/map" alt="Map">"#;
+        let links = parse_html_links(
+            &page_url,
+            html.to_owned(),
+            &LinkSource::all(),
+            &Default::default(),
+        );
+        assert_eq!(links[0].text, Some(String::from("The docs page")));
+        assert_eq!(links[0].rel, Some(String::from("nofollow")));
+        assert_eq!(links[1].text, Some(String::from("Map")));
+        assert_eq!(links[1].rel, None);
+        assert_eq!("iframe".parse(), Ok(LinkSource::Iframe));
+    }
+
+    #[test]
+    fn test_crawl_request() {
+        let request = parse_crawl_request("https://docs.rs\n").unwrap();
+        assert_eq!(request.url, "https://docs.rs");
+        assert!(request.include.is_empty());
+
+        let request = parse_crawl_request(
+            r#"{"url": "https://docs.rs", "max_pages": 20, "scope": "subdomains",
+                "include": ["/docs/**"], "exclude": ["*.pdf"]}"#,
+        )
+        .unwrap();
+        assert_eq!(request.url, "https://docs.rs");
+        assert_eq!(request.max_pages, Some(20));
+        assert_eq!(request.include, vec!["/docs/**"]);
+        assert_eq!(request.exclude, vec!["*.pdf"]);
+
+        let message = |content: &str| match parse_crawl_request(content) {
+            Err(e) => e.kind.message().to_owned(),
+            Ok(_) => String::new(),
+        };
+        assert!(message(r#"{"include": []}"#).contains("missing field `url`"));
+        assert!(message(r#"{"url": "https://docs.rs", "max_page": 2}"#)
+            .contains("unknown field `max_page`"));
+        assert!(message(r#"{"url": "https://docs.rs", "scope": "world"}"#)
+            .contains("unknown variant `world`"));
+    }
 }
@@ -0,0 +1,302 @@
+use crate::json::PolitenessJson;
+
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use reqwest::{StatusCode, Url};
+
+use serde::{Deserialize, Serialize};
+
+use std::collections::HashMap;
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+// Settings of the politeness layer for one crawl.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct PolitenessConfig {
+    #[serde(rename = "minDelayMs", with = "crate::options::millis")]
+    pub min_delay: Duration,
+    #[serde(rename = "maxDelayMs", with = "crate::options::millis")]
+    pub max_delay: Duration,
+    pub max_in_flight: usize,
+}
+
+impl Default for PolitenessConfig {
+    fn default() -> Self {
+        PolitenessConfig {
+            min_delay: Duration::from_millis(0),
+            max_delay: Duration::from_secs(30),
+            max_in_flight: 4,
+        }
+    }
+}
+
+// What happened to a request sent through the scheduler.
+pub enum Outcome {
+    Response {
+        status: StatusCode,
+        retry_after: Option<Duration>,
+    },
+    Failed,
+}
+
+impl Outcome {
+    // Builds the outcome of a response from its status code and headers.
+    pub fn from_response(status: StatusCode, headers: &HeaderMap) -> Self {
+        Outcome::Response {
+            status,
+            retry_after: headers
+                .get(RETRY_AFTER)
+                .and_then(|value| value.to_str().ok())
+                .and_then(parse_retry_after),
+        }
+    }
+
+    // Checks if the host asked the crawler to slow down.
+    pub fn is_throttled(&self) -> bool {
+        match self {
+            Outcome::Response { status, .. } => {
+                *status == StatusCode::TOO_MANY_REQUESTS
+                    || *status == StatusCode::SERVICE_UNAVAILABLE
+            }
+            Outcome::Failed => false,
+        }
+    }
+}
+
+// Authorization to send one request to a host, to give back to the scheduler.
+pub struct Permit {
+    host: String,
+    sent_at: Instant,
+}
+
+// State of the requests sent to a single host.
+struct HostState {
+    min_delay: Duration,
+    delay: Duration,
+    next_request: Instant,
+    in_flight: usize,
+}
+
+#[derive(Default)]
+struct Counters {
+    requests: usize,
+    throttled: usize,
+    failures: usize,
+    waited: Duration,
+    max_delay_reached: Duration,
+}
+
+// Makes the crawling threads wait for their turn so that each host gets a minimum delay
+// between requests and a bounded number of concurrent requests. The delay adapts to
+// the latency of the host and backs off when it answers 429 or 503.
+pub struct Scheduler {
+    config: PolitenessConfig,
+    hosts: Mutex<(HashMap<String, HostState>, Counters)>,
+    released: Condvar,
+}
+
+impl Scheduler {
+    // Creates a scheduler with the settings of a crawl. The Crawl-delay of each host is added
+    // once its robots.txt is read.
+    pub fn new(mut config: PolitenessConfig) -> Self {
+        config.max_delay = config.max_delay.max(config.min_delay);
+        config.max_in_flight = config.max_in_flight.max(1);
+        Scheduler {
+            config,
+            hosts: Mutex::new((HashMap::new(), Counters::default())),
+            released: Condvar::new(),
+        }
+    }
+
+    // Makes the minimum delay of the host of the url at least its robots.txt Crawl-delay.
+    pub fn set_crawl_delay(&self, url: &Url, crawl_delay: Option<Duration>) {
+        let host = url.host_str().unwrap_or_default().to_owned();
+        let mut guard = self.hosts.lock().unwrap();
+        let state = guard.0.entry(host).or_insert_with(|| self.host_state());
+        if let Some(crawl_delay) = crawl_delay {
+            state.min_delay = state.min_delay.max(crawl_delay);
+            state.delay = state.delay.max(crawl_delay);
+        }
+    }
+
+    // Returns the state of a host no request was sent to yet.
+    fn host_state(&self) -> HostState {
+        HostState {
+            min_delay: self.config.min_delay,
+            delay: self.config.min_delay,
+            next_request: Instant::now(),
+            in_flight: 0,
+        }
+    }
+
+    // Blocks the calling thread until a request can be sent to the host of the url.
+    pub fn acquire(&self, url: &Url) -> Permit {
+        let host = url.host_str().unwrap_or_default().to_owned();
+        let started = Instant::now();
+        let mut guard = self.hosts.lock().unwrap();
+        loop {
+            let (hosts, counters) = &mut *guard;
+            let state = hosts
+                .entry(host.clone())
+                .or_insert_with(|| self.host_state());
+            let now = Instant::now();
+            if state.in_flight < self.config.max_in_flight && state.next_request <= now {
+                state.in_flight += 1;
+                state.next_request = now + state.delay;
+                counters.requests += 1;
+                counters.waited += now - started;
+                return Permit { host, sent_at: now };
+            }
+            // Sleeps until the next slot of the host, or until another request is released.
+            guard = if state.in_flight < self.config.max_in_flight {
+                let timeout = state.next_request - now;
+                self.released.wait_timeout(guard, timeout).unwrap().0
+            } else {
+                self.released.wait(guard).unwrap()
+            };
+        }
+    }
+
+    // Gives a permit back and adapts the delay of its host to the outcome of the request.
+    pub fn release(&self, permit: Permit, outcome: &Outcome) {
+        let latency = permit.sent_at.elapsed();
+        let mut guard = self.hosts.lock().unwrap();
+        let (hosts, counters) = &mut *guard;
+        if let Some(state) = hosts.get_mut(&permit.host) {
+            state.in_flight -= 1;
+            state.delay = match outcome {
+                _ if outcome.is_throttled() => {
+                    counters.throttled += 1;
+                    (state.delay * 2).max(Duration::from_secs(1))
+                }
+                Outcome::Failed => {
+                    counters.failures += 1;
+                    state.delay * 3 / 2
+                }
+                // Slow answers mean a busy host: moves the delay towards the latency.
+                Outcome::Response { .. } => (state.delay * 3 + latency) / 4,
+            }
+            .max(state.min_delay)
+            .min(self.config.max_delay.max(state.min_delay));
+
+            // Retry-After is followed up to the maximum delay, so that a host cannot hold a
+            // crawl thread for longer.
+            let mut next_request = Instant::now() + state.delay;
+            if let Outcome::Response {
+                retry_after: Some(retry_after),
+                ..
+            } = outcome
+            {
+                let retry_at =
+                    Instant::now().checked_add((*retry_after).min(self.config.max_delay));
+                next_request = next_request.max(retry_at.unwrap_or(next_request));
+            }
+            state.next_request = state.next_request.max(next_request);
+            counters.max_delay_reached = counters.max_delay_reached.max(state.delay);
+        }
+        self.released.notify_all();
+    }
+
+    // Returns the statistics of the requests sent so far.
+    pub fn stats(&self) -> PolitenessJson {
+        let guard = self.hosts.lock().unwrap();
+        let (hosts, counters) = &*guard;
+        PolitenessJson {
+            requests: counters.requests,
+            throttled_responses: counters.throttled,
+            failed_requests: counters.failures,
+            total_wait_ms: counters.waited.as_millis() as u64,
+            min_delay_ms: self.config.min_delay.as_millis() as u64,
+            max_delay_reached_ms: counters.max_delay_reached.as_millis() as u64,
+            current_delays_ms: hosts
+                .iter()
+                .map(|(host, state)| (host.clone(), state.delay.as_millis() as u64))
+                .collect(),
+            max_in_flight: self.config.max_in_flight,
+        }
+    }
+}
+
+// Reads a Retry-After header, given either as a number of seconds or as an http date.
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    match value.trim().parse::<u64>() {
+        Ok(seconds) => Some(Duration::from_secs(seconds)),
+        Err(_) => httpdate::parse_http_date(value.trim())
+            .ok()
+            .and_then(|date| date.duration_since(SystemTime::now()).ok()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_retry_after_parsing() {
+        assert_eq!(parse_retry_after("120"), Some(Duration::from_secs(120)));
+        assert_eq!(parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"), None);
+        assert_eq!(parse_retry_after("soon"), None);
+    }
+
+    #[test]
+    fn test_adaptive_delay() {
+        let config = PolitenessConfig {
+            min_delay: Duration::from_millis(10),
+            // Caps the back-off of the 429 below, so that the test only waits 50ms.
+            max_delay: Duration::from_millis(50),
+            max_in_flight: 2,
+        };
+        let scheduler = Scheduler::new(config);
+        let url = Url::parse("https://docs.rs/").unwrap();
+        scheduler.set_crawl_delay(&url, Some(Duration::from_millis(20)));
+
+        let permit = scheduler.acquire(&url);
+        let throttled = Outcome::Response {
+            status: StatusCode::TOO_MANY_REQUESTS,
+            retry_after: None,
+        };
+        assert!(throttled.is_throttled());
+        scheduler.release(permit, &throttled);
+
+        let stats = scheduler.stats();
+        assert_eq!(stats.requests, 1);
+        assert_eq!(stats.throttled_responses, 1);
+        assert_eq!(stats.min_delay_ms, 10);
+        assert_eq!(stats.current_delays_ms["docs.rs"], 50);
+        assert_eq!(stats.max_delay_reached_ms, 50);
+
+        // A successful request moves the delay back towards the latency of the host.
+        let permit = scheduler.acquire(&url);
+        let ok = Outcome::Response {
+            status: StatusCode::OK,
+            retry_after: None,
+        };
+        scheduler.release(permit, &ok);
+        let stats = scheduler.stats();
+        assert!(stats.current_delays_ms["docs.rs"] < 50);
+        assert!(stats.total_wait_ms >= 40);
+    }
+
+    #[test]
+    fn test_retry_after_cap() {
+        let config = PolitenessConfig {
+            min_delay: Duration::from_millis(0),
+            max_delay: Duration::from_millis(50),
+            max_in_flight: 1,
+        };
+        let scheduler = Scheduler::new(config);
+        let url = Url::parse("https://docs.rs/").unwrap();
+
+        let mut headers = HeaderMap::new();
+        headers.insert(RETRY_AFTER, "18446744073709551615".parse().unwrap());
+        let outcome = Outcome::from_response(StatusCode::SERVICE_UNAVAILABLE, &headers);
+        let permit = scheduler.acquire(&url);
+        scheduler.release(permit, &outcome);
+
+        // The next request waits for the maximum delay, not for the Retry-After header.
+        let started = Instant::now();
+        let permit = scheduler.acquire(&url);
+        assert!(started.elapsed() < Duration::from_secs(1));
+        scheduler.release(permit, &Outcome::Failed);
+    }
+}
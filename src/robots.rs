@@ -0,0 +1,230 @@
+use reqwest::Url;
+
+use serde::{Deserialize, Serialize};
+
+use std::time::Duration;
+
+// Rules of a robots.txt file that apply to a given user-agent.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Default)]
+pub struct Robots {
+    pub user_agent: String,
+    rules: Vec<Rule>,
+    crawl_delay: Option<f64>,
+    #[serde(default)]
+    sitemaps: Vec<String>,
+}
+
+// An Allow or Disallow line, with its path pattern.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+struct Rule {
+    pattern: String,
+    allow: bool,
+}
+
+// A group of records sharing the same User-agent lines.
+#[derive(Default)]
+struct Group {
+    agents: Vec<String>,
+    rules: Vec<Rule>,
+    crawl_delay: Option<f64>,
+}
+
+impl Robots {
+    // Rules allowing everything, used when a site has no usable robots.txt.
+    pub fn allow_all(user_agent: &str) -> Self {
+        Robots {
+            user_agent: user_agent.to_owned(),
+            ..Default::default()
+        }
+    }
+
+    // Parses a robots.txt file and keeps the group that best matches the user-agent,
+    // falling back on the '*' group. The Sitemap lines apply to every user-agent.
+    pub fn parse(content: &str, user_agent: &str) -> Self {
+        let mut groups: Vec<Group> = vec![];
+        let mut sitemaps = vec![];
+        let mut reading_agents = false;
+
+        for line in content.lines() {
+            // Strips comments and splits the line into its field and value.
+            let line = line.split('#').next().unwrap_or("").trim();
+            let mut parts = line.splitn(2, ':');
+            let field = parts.next().unwrap_or("").trim().to_lowercase();
+            let value = match parts.next() {
+                Some(value) => value.trim(),
+                None => continue,
+            };
+
+            match field.as_str() {
+                "user-agent" => {
+                    if !reading_agents {
+                        groups.push(Group::default());
+                    }
+                    reading_agents = true;
+                    if let Some(group) = groups.last_mut() {
+                        group.agents.push(value.to_lowercase());
+                    }
+                }
+                "allow" | "disallow" => {
+                    reading_agents = false;
+                    // An empty Disallow line means that everything is allowed.
+                    if let (Some(group), false) = (groups.last_mut(), value.is_empty()) {
+                        group.rules.push(Rule {
+                            pattern: value.to_owned(),
+                            allow: field == "allow",
+                        });
+                    }
+                }
+                "crawl-delay" => {
+                    reading_agents = false;
+                    if let Some(group) = groups.last_mut() {
+                        group.crawl_delay = value.parse().ok();
+                    }
+                }
+                "sitemap" => {
+                    reading_agents = false;
+                    sitemaps.push(value.to_owned());
+                }
+                _ => reading_agents = false,
+            }
+        }
+
+        // The most specific user-agent token contained in ours wins over the wildcard group.
+        let agent = user_agent.to_lowercase();
+        let specificity = |group: &Group| {
+            group
+                .agents
+                .iter()
+                .filter(|token| token.as_str() == "*" || agent.contains(token.as_str()))
+                .map(|token| {
+                    if token.as_str() == "*" {
+                        0
+                    } else {
+                        token.len()
+                    }
+                })
+                .max()
+        };
+        let best = groups
+            .iter()
+            .filter_map(|group| specificity(group).map(|score| (score, group)))
+            .max_by_key(|(score, _)| *score)
+            .map(|(score, _)| score);
+
+        let mut robots = Robots::allow_all(user_agent);
+        robots.sitemaps = sitemaps;
+        if let Some(best) = best {
+            for group in groups.into_iter().filter(|g| specificity(g) == Some(best)) {
+                robots.rules.extend(group.rules);
+                robots.crawl_delay = robots.crawl_delay.or(group.crawl_delay);
+            }
+        }
+        robots
+    }
+
+    // Checks a url against the rules: the longest matching pattern decides, Allow wins ties.
+    pub fn is_allowed(&self, url: &Url) -> bool {
+        let path = match url.query() {
+            Some(query) => format!("{}?{}", url.path(), query),
+            None => url.path().to_owned(),
+        };
+        self.rules
+            .iter()
+            .filter(|rule| matches(&rule.pattern, &path))
+            .max_by_key(|rule| (rule.pattern.len(), rule.allow))
+            .map(|rule| rule.allow)
+            .unwrap_or(true)
+    }
+
+    // Returns the addresses of the sitemaps listed in the file.
+    pub fn sitemaps(&self) -> &[String] {
+        &self.sitemaps
+    }
+
+    pub fn crawl_delay(&self) -> Option<Duration> {
+        self.crawl_delay
+            .filter(|delay| *delay > 0.0)
+            .map(|delay| Duration::from_millis((delay * 1000.0) as u64))
+    }
+}
+
+// Matches a path against a robots.txt pattern, where '*' matches any sequence
+// of characters and a trailing '$' anchors the pattern at the end of the path.
+fn matches(pattern: &str, path: &str) -> bool {
+    let (pattern, anchored) = match pattern.ends_with('$') {
+        true => (&pattern[..pattern.len() - 1], true),
+        false => (pattern, false),
+    };
+    let mut pieces = pattern.split('*');
+    let first = pieces.next().unwrap_or("");
+    if !path.starts_with(first) {
+        return false;
+    }
+    let mut position = first.len();
+    let pieces: Vec<&str> = pieces.collect();
+    for (index, piece) in pieces.iter().enumerate() {
+        // The last piece of an anchored pattern has to match the end of the path.
+        if anchored && index == pieces.len() - 1 {
+            return path.len() >= position + piece.len() && path.ends_with(piece);
+        }
+        match path[position..].find(piece) {
+            Some(offset) => position += offset + piece.len(),
+            None => return false,
+        }
+    }
+    !anchored || position == path.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_robots_parsing() {
+        let content = "
+            # Comments are ignored
+            User-agent: *
+            Disallow: /private
+            Allow: /private/public-page
+            Crawl-delay: 2
+
+            User-agent: rust-crawler
+            User-agent: other-bot
+            Disallow: /no-rust
+
+            User-agent: BadBot
+            Disallow: /
+
+            Sitemap: https://docs.rs/sitemap.xml
+        ";
+        let robots = Robots::parse(content, "rust-crawler/0.1");
+        let url = |path| Url::parse(&format!("https://docs.rs{}", path)).unwrap();
+        assert!(!robots.is_allowed(&url("/no-rust/page")));
+        assert!(robots.is_allowed(&url("/private")));
+        assert_eq!(robots.crawl_delay(), None);
+
+        let robots = Robots::parse(content, "some-browser");
+        assert!(!robots.is_allowed(&url("/private/page")));
+        assert!(robots.is_allowed(&url("/private/public-page")));
+        assert!(robots.is_allowed(&url("/no-rust/page")));
+        assert_eq!(robots.crawl_delay(), Some(Duration::from_secs(2)));
+
+        let robots = Robots::parse(content, "BadBot");
+        assert!(!robots.is_allowed(&url("/")));
+        assert_eq!(robots.sitemaps(), ["https://docs.rs/sitemap.xml"]);
+    }
+
+    #[test]
+    fn test_robots_patterns() {
+        assert!(matches("/", "/anything"));
+        assert!(matches("/*.pdf$", "/docs/file.pdf"));
+        assert!(!matches("/*.pdf$", "/docs/file.pdf?download"));
+        assert!(matches("/search?*q=", "/search?lang=en&q=rust"));
+        assert!(!matches("/search", "/docs/search"));
+        assert!(matches("/page$", "/page"));
+        assert!(!matches("/page$", "/pages"));
+
+        let robots = Robots::parse("User-agent: *\nDisallow:\n", "rust-crawler");
+        assert!(robots.is_allowed(&Url::parse("https://docs.rs/").unwrap()));
+    }
+}
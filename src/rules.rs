@@ -0,0 +1,177 @@
+use crate::error::{CrawlError, ErrorType};
+use crate::Result;
+
+use regex::Regex;
+
+use reqwest::Url;
+
+use serde::{Deserialize, Serialize};
+
+// Include and exclude patterns matched against the path and the query of the urls of a crawl.
+// A pattern is a glob where '*' matches any characters but '/' and '**' any characters, or a
+// regular expression if it starts with "regex:".
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct UrlRules {
+    #[serde(default)]
+    pub include: Vec<String>,
+    #[serde(default)]
+    pub exclude: Vec<String>,
+}
+
+// Compiled include and exclude patterns.
+pub struct RuleSet {
+    include: Vec<Regex>,
+    exclude: Vec<Regex>,
+}
+
+impl UrlRules {
+    pub fn is_empty(&self) -> bool {
+        self.include.is_empty() && self.exclude.is_empty()
+    }
+
+    // Compiles the patterns, fails if one of the regular expressions is invalid.
+    pub fn compile(&self) -> Result<RuleSet> {
+        let compile = |patterns: &[String]| -> Result<Vec<Regex>> {
+            patterns.iter().map(|pattern| to_regex(pattern)).collect()
+        };
+        Ok(RuleSet {
+            include: compile(&self.include)?,
+            exclude: compile(&self.exclude)?,
+        })
+    }
+}
+
+impl RuleSet {
+    // Checks that a url matches one of the include patterns, if there are any, and none of
+    // the exclude patterns.
+    pub fn allows(&self, url: &Url) -> bool {
+        let target = target(url);
+        (self.include.is_empty() || self.include.iter().any(|rule| rule.is_match(&target)))
+            && !self.excludes(url)
+    }
+
+    // Checks that a url matches one of the exclude patterns.
+    pub fn excludes(&self, url: &Url) -> bool {
+        let target = target(url);
+        self.exclude.iter().any(|rule| rule.is_match(&target))
+    }
+}
+
+// Returns the part of a url the patterns are matched against, its path and its query.
+fn target(url: &Url) -> String {
+    match url.query() {
+        Some(query) => format!("{}?{}", url.path(), query),
+        None => url.path().to_owned(),
+    }
+}
+
+// Turns a pattern into a regular expression.
+fn to_regex(pattern: &str) -> Result<Regex> {
+    let expression = match pattern.strip_prefix("regex:") {
+        Some(expression) => expression.to_owned(),
+        None => glob_to_regex(pattern),
+    };
+    Regex::new(&expression).map_err(|_| {
+        CrawlError::new(ErrorType::InvalidRequest(format!(
+            "Invalid pattern `{}`, it is not a valid regular expression",
+            pattern
+        )))
+    })
+}
+
+// Translates a glob into a regular expression matching the whole path, or its last segment if
+// the glob has no '/', like "*.pdf". A glob without '?' matches the urls whatever their
+// query, otherwise the query has to match the part after the '?', where '*' matches any
+// characters. A trailing '/**' also matches the parent path, so "/docs/**" matches "/docs".
+fn glob_to_regex(glob: &str) -> String {
+    let (path, query) = match glob.split_once('?') {
+        Some((path, query)) => (path, Some(query)),
+        None => (glob, None),
+    };
+    let (path, any_child) = match path.strip_suffix("/**") {
+        Some(parent) => (parent, true),
+        None => (path, false),
+    };
+
+    let mut expression = String::from("^");
+    if !path.contains('/') {
+        expression.push_str("(.*/)?");
+    }
+    let pieces: Vec<String> = path
+        .split("**")
+        .map(|piece| {
+            let pieces: Vec<String> = piece.split('*').map(regex::escape).collect();
+            pieces.join("[^/]*")
+        })
+        .collect();
+    expression.push_str(&pieces.join(".*"));
+    if any_child {
+        expression.push_str("(/.*)?");
+    }
+    match query {
+        Some(query) => {
+            let pieces: Vec<String> = query.split('*').map(regex::escape).collect();
+            expression.push_str(&format!("\\?{}$", pieces.join(".*")));
+        }
+        None => expression.push_str("(\\?.*)?$"),
+    }
+    expression
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_url_rules() {
+        let rules = UrlRules {
+            include: vec![String::from("/docs/**"), String::from("regex:^/blog/\\d+$")],
+            exclude: vec![String::from("/search?*"), String::from("*.pdf")],
+        }
+        .compile()
+        .unwrap();
+        let allows =
+            |path: &str| rules.allows(&Url::parse(&format!("https://docs.rs{}", path)).unwrap());
+
+        assert!(allows("/docs/crate/page.html"));
+        assert!(allows("/docs/search"));
+        assert!(allows("/blog/12"));
+        assert!(!allows("/blog/latest"));
+        assert!(!allows("/"));
+        assert!(!allows("/docs/manual.pdf"));
+        assert!(!allows("/docs/../search?q=rust"));
+        assert!(!allows("/docs/manual.pdf?dl=1"));
+        assert!(rules.excludes(&Url::parse("https://docs.rs/search?q=rust").unwrap()));
+        assert!(!rules.excludes(&Url::parse("https://docs.rs/").unwrap()));
+        assert!(UrlRules::default()
+            .compile()
+            .unwrap()
+            .allows(&Url::parse("https://docs.rs/").unwrap()));
+
+        // A single '*' stays within a segment of the path, '**' goes through them.
+        let rules = UrlRules {
+            include: vec![String::from("/docs/*"), String::from("/blog/**")],
+            exclude: vec![String::from("/docs/*.html?*print*")],
+        }
+        .compile()
+        .unwrap();
+        let allows =
+            |path: &str| rules.allows(&Url::parse(&format!("https://docs.rs{}", path)).unwrap());
+        assert!(allows("/docs/crate"));
+        assert!(allows("/docs/crate?version=1"));
+        assert!(!allows("/docs/crate/page.html"));
+        assert!(!allows("/docs"));
+        assert!(allows("/blog"));
+        assert!(allows("/blog/2019/07/post"));
+        assert!(!allows("/blogs"));
+        assert!(allows("/docs/page.html?lang=en"));
+        assert!(!allows("/docs/page.html?mode=print"));
+
+        let invalid = UrlRules {
+            include: vec![String::from("regex:(")],
+            exclude: vec![],
+        };
+        assert!(invalid.compile().is_err());
+    }
+}
@@ -1,13 +1,17 @@
-use crate::crawler::crawl;
-use crate::database::DataBaseConnection;
 use crate::domain::Domain;
 use crate::error::{CrawlError, ErrorType};
-use crate::json::{NbJson, UrlsJson};
+use crate::export::export;
+use crate::jobs::{self, JobQueue};
+use crate::json::{BrokenLinksJson, DiffJson, GraphJson, NbJson, PageMetricsJson, UrlsJson};
+use crate::metrics::page_metrics;
+use crate::options::CrawlOptions;
 use crate::parsing;
+use crate::storage::{SharedStorage, Storage};
 use crate::Result;
 
 use futures::{future, future::Either, Future};
 
+use hyper::header::CONTENT_TYPE;
 use hyper::rt::run;
 use hyper::rt::Stream;
 use hyper::service::service_fn;
@@ -16,10 +20,15 @@ use hyper::{body::Body, Method, Request, Response, Server, StatusCode};
 use std::net::SocketAddr;
 use std::str::from_utf8;
 use std::string::ToString;
+use std::sync::Arc;
 
-// Starts the server, panics in case of error.
-pub fn start_server(addr: SocketAddr) {
-    let service = || service_fn(routing);
+// Starts the server, panics in case of error. The crawls run on the workers of the job queue,
+// so the server only answers from the storage.
+pub fn start_server(addr: SocketAddr, storage: SharedStorage, jobs: Arc<JobQueue>) {
+    let service = move || {
+        let (storage, jobs) = (storage.clone(), jobs.clone());
+        service_fn(move |req| routing(req, &storage, &jobs))
+    };
 
     let server = Server::bind(&addr)
         .serve(service)
@@ -29,62 +38,181 @@ pub fn start_server(addr: SocketAddr) {
 }
 
 // Dispatches the requests according to their methods and routes.
-fn routing(req: Request<Body>) -> impl Future<Item = Response<Body>, Error = hyper::Error> {
+fn routing(
+    req: Request<Body>,
+    storage: &SharedStorage,
+    jobs: &Arc<JobQueue>,
+) -> impl Future<Item = Response<Body>, Error = hyper::Error> {
     let resp = match (req.method(), req.uri().path()) {
-        (&Method::GET, "/urls") => handle_list(req),
-        (&Method::GET, "/nb-urls") => handle_nb(req),
+        (&Method::GET, "/urls") => handle_list(req, storage.as_ref()),
+        (&Method::GET, "/nb-urls") => handle_nb(req, storage.as_ref()),
+        (&Method::GET, "/broken-links") => handle_broken_links(req, storage.as_ref()),
+        (&Method::GET, "/graph") => handle_graph(req, storage.as_ref()),
+        (&Method::GET, "/export") => handle_export(req, storage.as_ref()),
+        (&Method::GET, "/metrics/pages") => handle_page_metrics(req, storage.as_ref()),
+        (&Method::GET, "/diff") => handle_diff(req, storage.as_ref()),
+        (&Method::GET, path) if path.starts_with("/jobs/") => {
+            handle_job(&path["/jobs/".len()..], storage.as_ref())
+        }
         (&Method::POST, "/crawl") => {
-            return Either::A(req.into_body().concat2().map(|content| {
+            let query = req.uri().query().map(ToOwned::to_owned);
+            let jobs = jobs.clone();
+            return Either::A(req.into_body().concat2().map(move |content| {
                 match from_utf8(&content) {
-                    Ok(data) => handle_crawl(data),
+                    Ok(data) => handle_crawl(data, query.as_deref(), &jobs),
                     Err(e) => Response::builder()
                         .status(StatusCode::UNPROCESSABLE_ENTITY)
                         .body(Body::from(format!("error: {}", e)))
                         .unwrap(),
                 }
-            }))
+            }));
         }
         (method, path) => handle_other_requests(method, path),
     };
     Either::B(future::ok(resp))
 }
 
-// Creates a Domain object from the post data, and tries to crawl the corresponding domain.
-fn handle_crawl(content: &str) -> Response<Body> {
-    let result =
-        Domain::new(&content).and_then(|domain| crawl(&domain).map(|json| json.to_string()));
+// Creates a Domain object from the post data, reads the crawl options from the query
+// and queues a job crawling the corresponding domain. Returns the job right away.
+fn handle_crawl(content: &str, query: Option<&str>, jobs: &JobQueue) -> Response<Body> {
+    let result = parsing::parse_crawl_request(content).and_then(|request| {
+        let domain = Domain::new(&request.url)?;
+        let options = CrawlOptions::from_request(query, request)?;
+        jobs.start_job(domain, options).map(|job| job.to_string())
+    });
+    send_status_or_err(StatusCode::ACCEPTED, result)
+}
+
+// Looks for a crawl job from its id, and returns its state.
+fn handle_job(id: &str, storage: &dyn Storage) -> Response<Body> {
+    send_ok_or_err(jobs::get_job(storage, id).map(|job| job.to_string()))
+}
+
+// Creates a Domain object from the query's domain parameter, and looks for a domain in the database.
+// Returns its urls, only the ones found in a kind of element if the source parameter is given.
+fn handle_list(req: Request<Body>, storage: &dyn Storage) -> Response<Body> {
+    let result = parsing::parse_domain(req.uri()).and_then(|name| {
+        let source = parsing::parse_source(req.uri())?;
+        storage
+            .get_pages(&name)
+            .ok_or_else(|| CrawlError::new(ErrorType::DomainNotCrawled))
+            .map(|mut pages| {
+                if let Some(source) = source {
+                    pages.retain(|page| page.sources.contains(&source));
+                }
+                let mut json = UrlsJson::new(&name, pages, storage.get_blocked(&name));
+                json.rules = storage.get_rules(&name);
+                json.crawled_at = storage.get_crawled_at(&name);
+                json.to_string()
+            })
+    });
+    send_ok_or_err(result)
+}
+
+// Creates a Domain object from the query's domain parameter, and looks for a domain in the database.
+// Returns its urls that could not be fetched, with the pages linking to them.
+fn handle_broken_links(req: Request<Body>, storage: &dyn Storage) -> Response<Body> {
+    let result = parsing::parse_domain(req.uri()).and_then(|name| {
+        storage
+            .get_pages(&name)
+            .ok_or_else(|| CrawlError::new(ErrorType::DomainNotCrawled))
+            .map(|pages| BrokenLinksJson::new(&name, &pages).to_string())
+    });
     send_ok_or_err(result)
 }
 
 // Creates a Domain object from the query's domain parameter, and looks for a domain in the database.
-// Returns its urls.
-fn handle_list(req: Request<Body>) -> Response<Body> {
+// Returns the links between its pages.
+fn handle_graph(req: Request<Body>, storage: &dyn Storage) -> Response<Body> {
     let result = parsing::parse_domain(req.uri()).and_then(|name| {
-        DataBaseConnection::new().and_then(|ref mut db| {
-            db.get_set(&name)
-                .ok_or_else(|| CrawlError::new(ErrorType::DomainNotCrawled))
-                .map(|set| UrlsJson::new(&name, set).to_string())
-        })
+        storage
+            .get_graph(&name)
+            .ok_or_else(|| CrawlError::new(ErrorType::DomainNotCrawled))
+            .map(|graph| GraphJson::new(&name, graph).to_string())
+    });
+    send_ok_or_err(result)
+}
+
+// Creates a Domain object from the query's domain parameter, and looks for a domain in the database.
+// Returns its link graph in the format of the format parameter.
+fn handle_export(req: Request<Body>, storage: &dyn Storage) -> Response<Body> {
+    let result = parsing::parse_domain(req.uri()).and_then(|name| {
+        let format = parsing::parse_format(req.uri())?;
+        let pages = storage.get_pages(&name).unwrap_or_default();
+        storage
+            .get_graph(&name)
+            .ok_or_else(|| CrawlError::new(ErrorType::DomainNotCrawled))
+            .map(|graph| {
+                (
+                    format,
+                    export(&GraphJson::new(&name, graph), &pages, format),
+                )
+            })
+    });
+    match result {
+        Ok((format, content)) => Response::builder()
+            .header(CONTENT_TYPE, format.content_type())
+            .body(Body::from(content))
+            .unwrap(),
+        Err(e) => send_ok_or_err(Err(e)),
+    }
+}
+
+// Creates a Domain object from the query's domain parameter, and looks for a domain in the database.
+// Returns the link metrics of its urls, computed from its link graph.
+fn handle_page_metrics(req: Request<Body>, storage: &dyn Storage) -> Response<Body> {
+    let result = parsing::parse_domain(req.uri()).and_then(|name| {
+        storage
+            .get_pages(&name)
+            .ok_or_else(|| CrawlError::new(ErrorType::DomainNotCrawled))
+            .map(|pages| {
+                let graph = storage.get_graph(&name).unwrap_or_default();
+                let metrics = page_metrics(&pages, &graph);
+                PageMetricsJson::new(&name, &pages, metrics).to_string()
+            })
+    });
+    send_ok_or_err(result)
+}
+
+// Creates a Domain object from the query's domain parameter, and looks for two snapshots of its
+// crawls in the database, the last two by default. Returns the differences between them.
+fn handle_diff(req: Request<Body>, storage: &dyn Storage) -> Response<Body> {
+    let result = parsing::parse_domain(req.uri()).and_then(|name| {
+        let (from, to) = parsing::parse_snapshots(req.uri())?;
+        let snapshots = storage.get_snapshots(&name);
+        let to = to
+            .or_else(|| snapshots.last().cloned())
+            .ok_or_else(|| CrawlError::new(ErrorType::DomainNotCrawled))?;
+        let from = from
+            .or_else(|| snapshots.iter().rev().find(|&&time| time < to).cloned())
+            .ok_or_else(|| CrawlError::new(ErrorType::SnapshotNotFound))?;
+        let snapshot = |time| {
+            storage
+                .get_snapshot(&name, time)
+                .ok_or_else(|| CrawlError::new(ErrorType::SnapshotNotFound))
+        };
+        let before = snapshot(from)?;
+        let after = snapshot(to)?;
+        Ok(DiffJson::new(&name, (from, &before), (to, &after), snapshots).to_string())
     });
     send_ok_or_err(result)
 }
 
 // Creates a Domain object from the query's domain parameter, and looks for the number of urls
 // stored for that domain in the database.
-fn handle_nb(req: Request<Body>) -> Response<Body> {
+fn handle_nb(req: Request<Body>, storage: &dyn Storage) -> Response<Body> {
     let result = parsing::parse_domain(req.uri()).and_then(|name| {
-        DataBaseConnection::new().and_then(|ref mut db| {
-            println!("name of the parameter: {}", name);
-            db.get_len(&name)
-                .ok_or_else(|| CrawlError::new(ErrorType::DomainNotCrawled))
-                .map(|(name, len)| {
-                    NbJson {
-                        nb_urls: len,
-                        domain_crawled: name.to_owned(),
-                    }
-                    .to_string()
-                })
-        })
+        println!("name of the parameter: {}", name);
+        storage
+            .get_len(&name)
+            .ok_or_else(|| CrawlError::new(ErrorType::DomainNotCrawled))
+            .map(|(name, len)| {
+                NbJson {
+                    nb_urls: len,
+                    domain_crawled: name.to_owned(),
+                }
+                .to_string()
+            })
     });
     send_ok_or_err(result)
 }
@@ -100,9 +228,14 @@ fn handle_other_requests(method: &Method, path: &str) -> Response<Body> {
 
 // Maps a result from one of the endpoint functions to an HTTP response.
 fn send_ok_or_err(result: Result<String>) -> Response<Body> {
+    send_status_or_err(StatusCode::OK, result)
+}
+
+// Maps a result to an HTTP response with the given status code in case of success.
+fn send_status_or_err(status: StatusCode, result: Result<String>) -> Response<Body> {
     match result {
         Ok(set) => Response::builder()
-            .status(StatusCode::OK)
+            .status(status)
             .body(Body::from(format!("{}\r\n", set)))
             .unwrap(),
         Err(CrawlError { kind, code }) => Response::builder()
@@ -0,0 +1,243 @@
+use crate::json::{PageJson, SitemapJson, SitemapReportJson};
+use crate::politeness::{Outcome, Scheduler};
+
+use flate2::read::GzDecoder;
+
+use reqwest::{Client, StatusCode, Url};
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io::Read;
+
+// Maximum number of sitemap files read for one crawl, sitemap indexes included.
+const MAX_SITEMAPS: usize = 50;
+
+// Maximum size of a sitemap file, before and after unzipping it, as in the sitemap protocol.
+const MAX_SITEMAP_SIZE: u64 = 50 * 1024 * 1024;
+
+// Content of sitemap files: the pages listed with their metadata, and the addresses
+// of sitemaps, either listed by a sitemap index or read.
+#[derive(Default, Debug, PartialEq)]
+pub struct Sitemap {
+    pub pages: Vec<(String, SitemapJson)>,
+    pub sitemaps: Vec<String>,
+}
+
+impl Sitemap {
+    // Parses a sitemap or a sitemap index, gzipped or not. Invalid files are empty.
+    pub fn parse(content: &[u8]) -> Self {
+        let mut sitemap = Sitemap::default();
+        let content = match decompress(content) {
+            Some(content) => content,
+            None => return sitemap,
+        };
+        let document = match roxmltree::Document::parse(&content) {
+            Ok(document) => document,
+            Err(_) => return sitemap,
+        };
+
+        for node in document
+            .root_element()
+            .children()
+            .filter(|n| n.is_element())
+        {
+            let field = |name: &str| {
+                node.children()
+                    .find(|child| child.tag_name().name() == name)
+                    .and_then(|child| child.text())
+                    .map(|text| text.trim().to_owned())
+                    .filter(|text| !text.is_empty())
+            };
+            let loc = match field("loc") {
+                Some(loc) => loc,
+                None => continue,
+            };
+            match node.tag_name().name() {
+                "url" => sitemap.pages.push((
+                    loc,
+                    SitemapJson {
+                        lastmod: field("lastmod"),
+                        priority: field("priority").and_then(|value| value.parse().ok()),
+                    },
+                )),
+                "sitemap" => sitemap.sitemaps.push(loc),
+                _ => (),
+            }
+        }
+        sitemap
+    }
+}
+
+// Returns the addresses where the sitemaps of a site are looked for: the ones listed in its
+// robots.txt file, and the default /sitemap.xml.
+pub fn sitemap_urls(url: &Url, listed: &[String]) -> Vec<Url> {
+    listed
+        .iter()
+        .map(String::as_str)
+        .chain(Some("/sitemap.xml"))
+        .filter_map(|sitemap| url.join(sitemap).ok())
+        .collect()
+}
+
+// Reads the sitemaps at the given addresses and the ones their sitemap indexes lead to.
+// Returns every page listed, along with the sitemaps that could be read.
+pub fn read_sitemaps(client: &Client, scheduler: &Scheduler, urls: Vec<Url>) -> Sitemap {
+    let mut result = Sitemap::default();
+    let mut seen = HashSet::new();
+    let mut queue: VecDeque<Url> = urls
+        .into_iter()
+        .filter(|url| seen.insert(url.clone()))
+        .collect();
+
+    while let Some(url) = queue.pop_front() {
+        if result.sitemaps.len() >= MAX_SITEMAPS {
+            break;
+        }
+        let sitemap = match fetch_sitemap(client, scheduler, &url) {
+            Some(content) => Sitemap::parse(&content),
+            None => continue,
+        };
+        if sitemap.pages.is_empty() && sitemap.sitemaps.is_empty() {
+            continue;
+        }
+        println!("Sitemap read: {}", url);
+        result.sitemaps.push(url.into_string());
+        result.pages.extend(sitemap.pages);
+        for child in sitemap.sitemaps {
+            if let Ok(child) = Url::parse(&child) {
+                if seen.insert(child.clone()) {
+                    queue.push_back(child);
+                }
+            }
+        }
+    }
+    result
+}
+
+// Compares the urls listed in the sitemaps with the urls of a crawl result.
+pub fn sitemap_report(
+    sitemaps: Vec<String>,
+    listed: &HashMap<String, SitemapJson>,
+    pages: &[PageJson],
+) -> SitemapReportJson {
+    let reached: HashSet<&str> = pages.iter().map(|page| page.url.as_str()).collect();
+    let mut not_reached: Vec<String> = listed
+        .keys()
+        .filter(|url| !reached.contains(url.as_str()))
+        .cloned()
+        .collect();
+    not_reached.sort();
+    SitemapReportJson {
+        sitemaps,
+        listed_urls: listed.len(),
+        reached_urls: listed.len() - not_reached.len(),
+        not_reached,
+        not_listed: pages
+            .iter()
+            .filter(|page| !listed.contains_key(&page.url))
+            .map(|page| page.url.clone())
+            .collect(),
+    }
+}
+
+// Gets the content of a sitemap file once the scheduler allows it.
+fn fetch_sitemap(client: &Client, scheduler: &Scheduler, url: &Url) -> Option<Vec<u8>> {
+    let permit = scheduler.acquire(url);
+    let (outcome, content) = match client.get(url.clone()).send() {
+        Ok(resp) => {
+            let outcome = Outcome::from_response(resp.status(), resp.headers());
+            let content = Some(resp)
+                .filter(|resp| resp.status() == StatusCode::OK)
+                .and_then(|resp| read_capped(resp, MAX_SITEMAP_SIZE));
+            (outcome, content)
+        }
+        Err(_) => (Outcome::Failed, None),
+    };
+    scheduler.release(permit, &outcome);
+    content
+}
+
+// Decodes the content of a sitemap, unzipping it first if it starts like a gzip file.
+fn decompress(content: &[u8]) -> Option<String> {
+    if content.starts_with(&[0x1f, 0x8b]) {
+        let unzipped = read_capped(GzDecoder::new(content), MAX_SITEMAP_SIZE)?;
+        String::from_utf8(unzipped).ok()
+    } else {
+        Some(String::from_utf8_lossy(content).into_owned())
+    }
+}
+
+// Reads everything from a reader, unless there are more than that many bytes.
+fn read_capped(reader: impl Read, limit: u64) -> Option<Vec<u8>> {
+    let mut content = vec![];
+    reader.take(limit + 1).read_to_end(&mut content).ok()?;
+    Some(content).filter(|content| content.len() as u64 <= limit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+
+    #[test]
+    fn test_sitemap_parsing() {
+        let urlset = r#"<?xml version="1.0" encoding="UTF-8"?>
+            <urlset xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">
+                <url>
+                    <loc>https://docs.rs/about</loc>
+                    <lastmod>2019-07-01</lastmod>
+                    <priority>0.8</priority>
+                </url>
+                <url><loc> https://docs.rs/releases </loc></url>
+                <url><lastmod>2019-07-01</lastmod></url>
+            </urlset>"#;
+        let mut encoder = GzEncoder::new(vec![], Compression::default());
+        encoder.write_all(urlset.as_bytes()).unwrap();
+        let gzipped = encoder.finish().unwrap();
+
+        let expected = Sitemap {
+            pages: vec![
+                (
+                    String::from("https://docs.rs/about"),
+                    SitemapJson {
+                        lastmod: Some(String::from("2019-07-01")),
+                        priority: Some(0.8),
+                    },
+                ),
+                (
+                    String::from("https://docs.rs/releases"),
+                    SitemapJson::default(),
+                ),
+            ],
+            sitemaps: vec![],
+        };
+        assert_eq!(Sitemap::parse(urlset.as_bytes()), expected);
+        assert_eq!(Sitemap::parse(&gzipped), expected);
+
+        let index = r#"<sitemapindex xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">
+                <sitemap><loc>https://docs.rs/sitemap-1.xml.gz</loc></sitemap>
+            </sitemapindex>"#;
+        assert_eq!(
+            Sitemap::parse(index.as_bytes()).sitemaps,
+            vec!["https://docs.rs/sitemap-1.xml.gz"]
+        );
+        assert_eq!(Sitemap::parse(b"<html>"), Sitemap::default());
+    }
+
+    #[test]
+    fn test_capped_reads() {
+        assert_eq!(read_capped(&b"12345"[..], 5), Some(b"12345".to_vec()));
+        assert_eq!(read_capped(&b"123456"[..], 5), None);
+
+        // A small file unzipping to more than the maximum size is not read.
+        let mut encoder = GzEncoder::new(vec![], Compression::best());
+        let zeros = vec![0; 1024 * 1024];
+        for _ in 0..=MAX_SITEMAP_SIZE / zeros.len() as u64 {
+            encoder.write_all(&zeros).unwrap();
+        }
+        let bomb = encoder.finish().unwrap();
+        assert!(bomb.len() < 1024 * 1024);
+        assert_eq!(decompress(&bomb), None);
+    }
+}
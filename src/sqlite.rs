@@ -0,0 +1,459 @@
+use crate::crawler::Checkpoint;
+use crate::json::{EdgeJson, JobJson, JobState, PageJson, SnapshotPageJson};
+use crate::options::CrawlOptions;
+use crate::robots::Robots;
+use crate::rules::UrlRules;
+use crate::storage::{
+    bare_page, now_secs, snapshots_kept, to_json, Storage, JOB_TTL, REFRESH_LOCK_TTL, ROBOTS_TTL,
+};
+use crate::Result;
+
+use rusqlite::types::ToSql;
+use rusqlite::{params, Connection, OptionalExtension, NO_PARAMS};
+use serde::de::DeserializeOwned;
+
+use std::collections::{HashMap, HashSet};
+use std::sync::{Mutex, MutexGuard};
+
+// Tables of the SQLite backend. The information about the pages, the links, the rules, the
+// options of the crawls, the robots.txt rules, the checkpoints and the jobs is stored as
+// JSON. Expiration times are in seconds since the Unix epoch.
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS urls (
+    domain TEXT NOT NULL, url TEXT NOT NULL, PRIMARY KEY (domain, url));
+CREATE TABLE IF NOT EXISTS pages (
+    domain TEXT NOT NULL, url TEXT NOT NULL, content TEXT NOT NULL, PRIMARY KEY (domain, url));
+CREATE TABLE IF NOT EXISTS blocked (
+    domain TEXT NOT NULL, url TEXT NOT NULL, PRIMARY KEY (domain, url));
+CREATE TABLE IF NOT EXISTS graphs (
+    domain TEXT NOT NULL, url TEXT NOT NULL, edges TEXT NOT NULL, PRIMARY KEY (domain, url));
+CREATE TABLE IF NOT EXISTS meta (
+    domain TEXT PRIMARY KEY, rules TEXT, crawled_at INTEGER);
+CREATE TABLE IF NOT EXISTS options (
+    domain TEXT PRIMARY KEY, content TEXT NOT NULL);
+CREATE TABLE IF NOT EXISTS refreshing (
+    domain TEXT PRIMARY KEY, expires_at INTEGER NOT NULL);
+CREATE TABLE IF NOT EXISTS robots (
+    domain TEXT PRIMARY KEY, content TEXT NOT NULL, expires_at INTEGER NOT NULL);
+CREATE TABLE IF NOT EXISTS snapshots (
+    domain TEXT NOT NULL, crawled_at INTEGER NOT NULL, PRIMARY KEY (domain, crawled_at));
+CREATE TABLE IF NOT EXISTS snapshot_pages (
+    domain TEXT NOT NULL, crawled_at INTEGER NOT NULL, url TEXT NOT NULL, content TEXT NOT NULL,
+    PRIMARY KEY (domain, crawled_at, url));
+-- The checkpoints used to belong to domains, they cannot be resumed by the jobs.
+DROP TABLE IF EXISTS checkpoints;
+CREATE TABLE IF NOT EXISTS job_checkpoints (
+    job_id TEXT PRIMARY KEY, content TEXT NOT NULL);
+CREATE TABLE IF NOT EXISTS jobs (
+    id TEXT PRIMARY KEY, content TEXT NOT NULL, expires_at INTEGER);
+";
+
+// SQLite storage backend, in a single database file.
+pub struct SqliteStorage {
+    connection: Mutex<Connection>,
+}
+
+impl SqliteStorage {
+    // Opens the database file at that path, ":memory:" for a database that is not saved, and
+    // creates its tables if needed.
+    pub fn open(path: &str) -> Result<Self> {
+        let connection = Connection::open(path)?;
+        connection.execute_batch(SCHEMA)?;
+        Ok(SqliteStorage {
+            connection: Mutex::new(connection),
+        })
+    }
+
+    // Locks the connection, even if a thread panicked while holding it.
+    fn connection(&self) -> MutexGuard<'_, Connection> {
+        self.connection
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    // Returns the column of each row of a query.
+    fn column(&self, sql: &str, params: &[&dyn ToSql]) -> Result<Vec<String>> {
+        let connection = self.connection();
+        let mut statement = connection.prepare(sql)?;
+        let rows = statement.query_map(params, |row| row.get(0))?;
+        Ok(rows.collect::<std::result::Result<_, _>>()?)
+    }
+
+    // Returns the url and the JSON column of each row of a query, deserialized.
+    fn by_url<T: DeserializeOwned>(
+        &self,
+        sql: &str,
+        params: &[&dyn ToSql],
+    ) -> Result<HashMap<String, T>> {
+        let connection = self.connection();
+        let mut statement = connection.prepare(sql)?;
+        let rows = statement.query_map(params, |row| Ok((row.get(0)?, row.get(1)?)))?;
+        let rows: Vec<(String, String)> = rows.collect::<std::result::Result<_, _>>()?;
+        Ok(rows
+            .into_iter()
+            .filter_map(|(url, content)| serde_json::from_str(&content).ok().map(|v| (url, v)))
+            .collect())
+    }
+
+    // Returns the JSON column of the first row of a query, deserialized, if any.
+    fn value<T: DeserializeOwned>(&self, sql: &str, params: &[&dyn ToSql]) -> Option<T> {
+        self.connection()
+            .query_row(sql, params, |row| row.get::<_, Option<String>>(0))
+            .optional()
+            .ok()??
+            .and_then(|content| serde_json::from_str(&content).ok())
+    }
+}
+
+// Returns the expiration time of a value stored now for that many seconds.
+fn expires_in(secs: usize) -> i64 {
+    (now_secs() + secs as u64) as i64
+}
+
+impl Storage for SqliteStorage {
+    fn get_set(&self, domain_name: &str) -> Option<HashSet<String>> {
+        let urls = self
+            .column("SELECT url FROM urls WHERE domain = ?1", &[&domain_name])
+            .ok()?;
+        if urls.is_empty() {
+            return None;
+        }
+        Some(urls.into_iter().collect())
+    }
+
+    fn get_len<'a>(&self, domain_name: &'a str) -> Option<(&'a str, usize)> {
+        let size: i64 = self
+            .connection()
+            .query_row(
+                "SELECT COUNT(*) FROM urls WHERE domain = ?1",
+                &[&domain_name],
+                |row| row.get(0),
+            )
+            .ok()?;
+        if size == 0 {
+            return None;
+        }
+        Some((domain_name, size as usize))
+    }
+
+    fn set(&self, domain_name: &str, domain_set: HashSet<String>) -> Result<()> {
+        println!("insertion in the database with name: {}", domain_name);
+        let mut connection = self.connection();
+        let transaction = connection.transaction()?;
+        transaction.execute("DELETE FROM urls WHERE domain = ?1", &[&domain_name])?;
+        for url in &domain_set {
+            transaction.execute(
+                "INSERT INTO urls (domain, url) VALUES (?1, ?2)",
+                params![domain_name, url],
+            )?;
+        }
+        Ok(transaction.commit()?)
+    }
+
+    fn get_pages(&self, domain_name: &str) -> Option<Vec<PageJson>> {
+        let urls = self.get_set(domain_name)?;
+        let mut stored: HashMap<String, PageJson> = self
+            .by_url(
+                "SELECT url, content FROM pages WHERE domain = ?1",
+                &[&domain_name],
+            )
+            .unwrap_or_default();
+        Some(
+            urls.into_iter()
+                .map(|url| stored.remove(&url).unwrap_or_else(|| bare_page(url)))
+                .collect(),
+        )
+    }
+
+    fn set_pages(&self, domain_name: &str, pages: &[PageJson]) -> Result<()> {
+        if pages.is_empty() {
+            return Ok(());
+        }
+        let fields = pages
+            .iter()
+            .map(|page| to_json(page).map(|content| (&page.url, content)))
+            .collect::<Result<Vec<(&String, String)>>>()?;
+        println!("insertion in the database with name: {}", domain_name);
+        let mut connection = self.connection();
+        let transaction = connection.transaction()?;
+        transaction.execute("DELETE FROM urls WHERE domain = ?1", &[&domain_name])?;
+        transaction.execute("DELETE FROM pages WHERE domain = ?1", &[&domain_name])?;
+        for (url, content) in &fields {
+            transaction.execute(
+                "INSERT OR IGNORE INTO urls (domain, url) VALUES (?1, ?2)",
+                params![domain_name, url],
+            )?;
+            transaction.execute(
+                "INSERT OR REPLACE INTO pages (domain, url, content) VALUES (?1, ?2, ?3)",
+                params![domain_name, url, content],
+            )?;
+        }
+        Ok(transaction.commit()?)
+    }
+
+    fn get_snapshots(&self, domain_name: &str) -> Vec<u64> {
+        let connection = self.connection();
+        let snapshots = connection
+            .prepare("SELECT crawled_at FROM snapshots WHERE domain = ?1 ORDER BY crawled_at")
+            .and_then(|mut statement| {
+                statement
+                    .query_map(&[&domain_name], |row| row.get::<_, i64>(0))?
+                    .collect::<std::result::Result<Vec<i64>, _>>()
+            });
+        snapshots
+            .map(|snapshots| snapshots.into_iter().map(|time| time as u64).collect())
+            .unwrap_or_default()
+    }
+
+    fn get_snapshot(
+        &self,
+        domain_name: &str,
+        crawled_at: u64,
+    ) -> Option<HashMap<String, SnapshotPageJson>> {
+        if !self.get_snapshots(domain_name).contains(&crawled_at) {
+            return None;
+        }
+        self.by_url(
+            "SELECT url, content FROM snapshot_pages WHERE domain = ?1 AND crawled_at = ?2",
+            params![domain_name, crawled_at as i64],
+        )
+        .ok()
+    }
+
+    fn add_snapshot(&self, domain_name: &str, crawled_at: u64, pages: &[PageJson]) -> Result<()> {
+        let fields = pages
+            .iter()
+            .map(|page| to_json(&SnapshotPageJson::new(page)).map(|content| (&page.url, content)))
+            .collect::<Result<Vec<(&String, String)>>>()?;
+        let crawled_at = crawled_at as i64;
+        let mut connection = self.connection();
+        let transaction = connection.transaction()?;
+        transaction.execute(
+            "DELETE FROM snapshot_pages WHERE domain = ?1 AND crawled_at = ?2",
+            params![domain_name, crawled_at],
+        )?;
+        transaction.execute(
+            "INSERT OR IGNORE INTO snapshots (domain, crawled_at) VALUES (?1, ?2)",
+            params![domain_name, crawled_at],
+        )?;
+        for (url, content) in &fields {
+            transaction.execute(
+                "INSERT INTO snapshot_pages (domain, crawled_at, url, content)
+                 VALUES (?1, ?2, ?3, ?4)",
+                params![domain_name, crawled_at, url, content],
+            )?;
+        }
+
+        // Only keeps the most recent snapshots.
+        let kept = snapshots_kept() as i64;
+        let outdated = "SELECT crawled_at FROM snapshots WHERE domain = ?1
+                        ORDER BY crawled_at DESC LIMIT -1 OFFSET ?2";
+        transaction.execute(
+            &format!(
+                "DELETE FROM snapshot_pages WHERE domain = ?1 AND crawled_at IN ({})",
+                outdated
+            ),
+            params![domain_name, kept],
+        )?;
+        transaction.execute(
+            &format!(
+                "DELETE FROM snapshots WHERE domain = ?1 AND crawled_at IN ({})",
+                outdated
+            ),
+            params![domain_name, kept],
+        )?;
+        Ok(transaction.commit()?)
+    }
+
+    fn get_blocked(&self, domain_name: &str) -> HashSet<String> {
+        self.column("SELECT url FROM blocked WHERE domain = ?1", &[&domain_name])
+            .map(|urls| urls.into_iter().collect())
+            .unwrap_or_default()
+    }
+
+    fn set_blocked(&self, domain_name: &str, blocked_set: HashSet<String>) -> Result<()> {
+        let mut connection = self.connection();
+        let transaction = connection.transaction()?;
+        transaction.execute("DELETE FROM blocked WHERE domain = ?1", &[&domain_name])?;
+        for url in &blocked_set {
+            transaction.execute(
+                "INSERT INTO blocked (domain, url) VALUES (?1, ?2)",
+                params![domain_name, url],
+            )?;
+        }
+        Ok(transaction.commit()?)
+    }
+
+    fn get_graph(&self, domain_name: &str) -> Option<HashMap<String, Vec<EdgeJson>>> {
+        self.by_url(
+            "SELECT url, edges FROM graphs WHERE domain = ?1",
+            &[&domain_name],
+        )
+        .ok()
+        .filter(|graph| !graph.is_empty())
+    }
+
+    fn set_graph(&self, domain_name: &str, graph: &HashMap<String, Vec<EdgeJson>>) -> Result<()> {
+        let fields = graph
+            .iter()
+            .map(|(url, edges)| to_json(edges).map(|edges| (url, edges)))
+            .collect::<Result<Vec<(&String, String)>>>()?;
+        let mut connection = self.connection();
+        let transaction = connection.transaction()?;
+        transaction.execute("DELETE FROM graphs WHERE domain = ?1", &[&domain_name])?;
+        for (url, edges) in &fields {
+            transaction.execute(
+                "INSERT INTO graphs (domain, url, edges) VALUES (?1, ?2, ?3)",
+                params![domain_name, url, edges],
+            )?;
+        }
+        Ok(transaction.commit()?)
+    }
+
+    fn get_rules(&self, domain_name: &str) -> Option<UrlRules> {
+        self.value("SELECT rules FROM meta WHERE domain = ?1", &[&domain_name])
+    }
+
+    fn set_rules(&self, domain_name: &str, rules: &UrlRules) -> Result<()> {
+        let content = to_json(rules)?;
+        self.connection().execute(
+            "INSERT INTO meta (domain, rules) VALUES (?1, ?2)
+             ON CONFLICT (domain) DO UPDATE SET rules = excluded.rules",
+            params![domain_name, content],
+        )?;
+        Ok(())
+    }
+
+    fn get_options(&self, domain_name: &str) -> Option<CrawlOptions> {
+        self.value(
+            "SELECT content FROM options WHERE domain = ?1",
+            &[&domain_name],
+        )
+    }
+
+    fn set_options(&self, domain_name: &str, options: &CrawlOptions) -> Result<()> {
+        let content = to_json(options)?;
+        self.connection().execute(
+            "INSERT OR REPLACE INTO options (domain, content) VALUES (?1, ?2)",
+            params![domain_name, content],
+        )?;
+        Ok(())
+    }
+
+    fn get_crawled_at(&self, domain_name: &str) -> Option<u64> {
+        self.connection()
+            .query_row(
+                "SELECT crawled_at FROM meta WHERE domain = ?1",
+                &[&domain_name],
+                |row| row.get::<_, Option<i64>>(0),
+            )
+            .optional()
+            .ok()??
+            .map(|crawled_at| crawled_at as u64)
+    }
+
+    fn set_crawled_at(&self, domain_name: &str, crawled_at: u64) -> Result<()> {
+        self.connection().execute(
+            "INSERT INTO meta (domain, crawled_at) VALUES (?1, ?2)
+             ON CONFLICT (domain) DO UPDATE SET crawled_at = excluded.crawled_at",
+            params![domain_name, crawled_at as i64],
+        )?;
+        Ok(())
+    }
+
+    fn lock_refresh(&self, domain_name: &str) -> Result<bool> {
+        let connection = self.connection();
+        connection.execute(
+            "DELETE FROM refreshing WHERE domain = ?1 AND expires_at <= ?2",
+            params![domain_name, now_secs() as i64],
+        )?;
+        let locked = connection.execute(
+            "INSERT OR IGNORE INTO refreshing (domain, expires_at) VALUES (?1, ?2)",
+            params![domain_name, expires_in(REFRESH_LOCK_TTL)],
+        )?;
+        Ok(locked > 0)
+    }
+
+    fn unlock_refresh(&self, domain_name: &str) -> Result<()> {
+        self.connection()
+            .execute("DELETE FROM refreshing WHERE domain = ?1", &[&domain_name])?;
+        Ok(())
+    }
+
+    fn get_robots(&self, domain_name: &str, user_agent: &str) -> Option<Robots> {
+        self.value::<Robots>(
+            "SELECT content FROM robots WHERE domain = ?1 AND expires_at > ?2",
+            params![domain_name, now_secs() as i64],
+        )
+        .filter(|robots| robots.user_agent == user_agent)
+    }
+
+    fn set_robots(&self, domain_name: &str, robots: &Robots) -> Result<()> {
+        let content = to_json(robots)?;
+        self.connection().execute(
+            "INSERT OR REPLACE INTO robots (domain, content, expires_at) VALUES (?1, ?2, ?3)",
+            params![domain_name, content, expires_in(ROBOTS_TTL)],
+        )?;
+        Ok(())
+    }
+
+    fn get_checkpoint(&self, job_id: &str) -> Option<Checkpoint> {
+        self.value(
+            "SELECT content FROM job_checkpoints WHERE job_id = ?1",
+            &[&job_id],
+        )
+    }
+
+    fn set_checkpoint(&self, job_id: &str, checkpoint: &Checkpoint) -> Result<()> {
+        let content = to_json(checkpoint)?;
+        self.connection().execute(
+            "INSERT OR REPLACE INTO job_checkpoints (job_id, content) VALUES (?1, ?2)",
+            params![job_id, content],
+        )?;
+        Ok(())
+    }
+
+    fn delete_checkpoint(&self, job_id: &str) -> Result<()> {
+        self.connection()
+            .execute("DELETE FROM job_checkpoints WHERE job_id = ?1", &[&job_id])?;
+        Ok(())
+    }
+
+    fn get_job(&self, id: &str) -> Option<JobJson> {
+        self.value(
+            "SELECT content FROM jobs WHERE id = ?1 AND (expires_at IS NULL OR expires_at > ?2)",
+            params![id, now_secs() as i64],
+        )
+    }
+
+    fn set_job(&self, job: &JobJson) -> Result<()> {
+        let content = to_json(job)?;
+        let expires_at = match job.state {
+            JobState::Queued | JobState::Running => None,
+            JobState::Done | JobState::Failed => Some(expires_in(JOB_TTL)),
+        };
+        let connection = self.connection();
+        connection.execute(
+            "DELETE FROM jobs WHERE expires_at <= ?1",
+            &[&(now_secs() as i64)],
+        )?;
+        connection.execute(
+            "INSERT OR REPLACE INTO jobs (id, content, expires_at) VALUES (?1, ?2, ?3)",
+            params![job.id, content, expires_at],
+        )?;
+        Ok(())
+    }
+
+    fn delete_job(&self, id: &str) -> Result<()> {
+        self.connection()
+            .execute("DELETE FROM jobs WHERE id = ?1", &[&id])?;
+        Ok(())
+    }
+
+    fn get_active_jobs(&self) -> Result<HashSet<String>> {
+        Ok(self
+            .column("SELECT id FROM jobs WHERE expires_at IS NULL", NO_PARAMS)?
+            .into_iter()
+            .collect())
+    }
+}
@@ -0,0 +1,339 @@
+use crate::crawler::Checkpoint;
+use crate::database::RedisStorage;
+use crate::error::{CrawlError, ErrorType};
+use crate::json::{EdgeJson, JobJson, PageJson, SnapshotPageJson};
+use crate::memory::MemoryStorage;
+use crate::options::CrawlOptions;
+use crate::robots::Robots;
+use crate::rules::UrlRules;
+use crate::sqlite::SqliteStorage;
+use crate::Result;
+
+use serde::Serialize;
+
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Number of seconds the robots.txt rules of a domain stay cached.
+pub const ROBOTS_TTL: usize = 24 * 60 * 60;
+
+// Number of seconds a finished job stays available.
+pub const JOB_TTL: usize = 7 * 24 * 60 * 60;
+
+// Number of seconds a domain is kept from being refreshed again in the background while its
+// refresh runs, in case the refresh never ends.
+pub const REFRESH_LOCK_TTL: usize = 60 * 60;
+
+// Number of snapshots of the crawls of a domain kept when SNAPSHOTS_KEPT is not set.
+const SNAPSHOTS_KEPT: usize = 10;
+
+// Path of the database file of the SQLite backend when SQLITE_PATH is not set.
+const SQLITE_PATH: &str = "crawler.db";
+
+// A storage backend shared by the server handlers, the jobs and the crawls.
+pub type SharedStorage = Arc<dyn Storage>;
+
+// Where the crawled domains and the crawl jobs are stored: the sets of urls of the domains
+// and the information about each of them, the snapshots of their crawls, the jobs and the
+// checkpoints of their running crawls.
+pub trait Storage: Send + Sync {
+    // Returns a set of urls if it exists.
+    fn get_set(&self, domain_name: &str) -> Option<HashSet<String>>;
+
+    // Returns the length of a set of urls if it exists.
+    fn get_len<'a>(&self, domain_name: &'a str) -> Option<(&'a str, usize)>;
+
+    // Replaces a set of urls.
+    fn set(&self, domain_name: &str, domain_set: HashSet<String>) -> Result<()>;
+
+    // Returns the information stored about each url of a crawled domain, if it exists.
+    fn get_pages(&self, domain_name: &str) -> Option<Vec<PageJson>>;
+
+    // Replaces the set of urls of a domain along with the information about each of them.
+    fn set_pages(&self, domain_name: &str, pages: &[PageJson]) -> Result<()>;
+
+    // Returns the times of the stored snapshots of the crawls of a domain, oldest first.
+    fn get_snapshots(&self, domain_name: &str) -> Vec<u64>;
+
+    // Returns the outcome of the request of each url in a snapshot of a domain, if it exists.
+    fn get_snapshot(
+        &self,
+        domain_name: &str,
+        crawled_at: u64,
+    ) -> Option<HashMap<String, SnapshotPageJson>>;
+
+    // Stores a snapshot of the crawl of a domain, named after the time of the crawl, and only
+    // keeps the last snapshots_kept() snapshots.
+    fn add_snapshot(&self, domain_name: &str, crawled_at: u64, pages: &[PageJson]) -> Result<()>;
+
+    // Returns the urls that robots.txt kept the crawler from visiting, if any.
+    fn get_blocked(&self, domain_name: &str) -> HashSet<String>;
+
+    // Replaces the urls blocked by robots.txt next to the set of crawled urls.
+    fn set_blocked(&self, domain_name: &str, blocked_set: HashSet<String>) -> Result<()>;
+
+    // Returns the links found in each page of a crawled domain, if they were recorded.
+    fn get_graph(&self, domain_name: &str) -> Option<HashMap<String, Vec<EdgeJson>>>;
+
+    // Replaces the links found in each page of a domain.
+    fn set_graph(&self, domain_name: &str, graph: &HashMap<String, Vec<EdgeJson>>) -> Result<()>;
+
+    // Returns the include and exclude rules a domain was crawled with, if they were recorded.
+    fn get_rules(&self, domain_name: &str) -> Option<UrlRules>;
+
+    // Records the include and exclude rules a domain was crawled with.
+    fn set_rules(&self, domain_name: &str, rules: &UrlRules) -> Result<()>;
+
+    // Returns the options of the last crawl of a domain, if they were recorded.
+    fn get_options(&self, domain_name: &str) -> Option<CrawlOptions>;
+
+    // Records the options of the last crawl of a domain.
+    fn set_options(&self, domain_name: &str, options: &CrawlOptions) -> Result<()>;
+
+    // Returns when a domain was last crawled, in seconds since the Unix epoch, if it was recorded.
+    fn get_crawled_at(&self, domain_name: &str) -> Option<u64>;
+
+    // Records when a domain was crawled.
+    fn set_crawled_at(&self, domain_name: &str, crawled_at: u64) -> Result<()>;
+
+    // Marks a domain as being refreshed in the background for REFRESH_LOCK_TTL seconds at
+    // most. Returns false if it already is.
+    fn lock_refresh(&self, domain_name: &str) -> Result<bool>;
+
+    // Lets the domain be refreshed again.
+    fn unlock_refresh(&self, domain_name: &str) -> Result<()>;
+
+    // Returns the cached robots.txt rules of a domain if they were parsed for that user-agent.
+    fn get_robots(&self, domain_name: &str, user_agent: &str) -> Option<Robots>;
+
+    // Caches the robots.txt rules of a domain for ROBOTS_TTL seconds.
+    fn set_robots(&self, domain_name: &str, robots: &Robots) -> Result<()>;
+
+    // Returns the last checkpoint of the interrupted crawl of a job, if any.
+    fn get_checkpoint(&self, job_id: &str) -> Option<Checkpoint>;
+
+    // Replaces the checkpoint of the running crawl of a job.
+    fn set_checkpoint(&self, job_id: &str, checkpoint: &Checkpoint) -> Result<()>;
+
+    // Removes the checkpoint of the crawl of a job once it is finished or failed.
+    fn delete_checkpoint(&self, job_id: &str) -> Result<()>;
+
+    // Returns a job from its id.
+    fn get_job(&self, id: &str) -> Option<JobJson>;
+
+    // Saves the state of a job. Finished jobs expire after JOB_TTL seconds, the others are
+    // kept in the set of active jobs.
+    fn set_job(&self, job: &JobJson) -> Result<()>;
+
+    // Removes a job, with its place in the set of active jobs.
+    fn delete_job(&self, id: &str) -> Result<()>;
+
+    // Returns the ids of the jobs that are queued or running.
+    fn get_active_jobs(&self) -> Result<HashSet<String>>;
+
+    // Moves the data written by older versions to the current layout of the storage. Returns
+    // the number of domains and jobs moved.
+    fn migrate(&self) -> Result<usize> {
+        Ok(0)
+    }
+}
+
+// The available storage backends.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Backend {
+    Redis,
+    Memory,
+    Sqlite,
+}
+
+impl FromStr for Backend {
+    type Err = ();
+
+    fn from_str(value: &str) -> std::result::Result<Self, Self::Err> {
+        match value {
+            "redis" => Ok(Backend::Redis),
+            "memory" => Ok(Backend::Memory),
+            "sqlite" => Ok(Backend::Sqlite),
+            _ => Err(()),
+        }
+    }
+}
+
+// Opens the storage backend chosen with the STORAGE_BACKEND environment variable, Redis by
+// default.
+pub fn open() -> Result<SharedStorage> {
+    let backend = env::var("STORAGE_BACKEND").unwrap_or_else(|_| String::from("redis"));
+    let backend = backend.parse().map_err(|_| {
+        println!("unknown storage backend: {}", backend);
+        CrawlError::new(ErrorType::EnvError)
+    })?;
+    Ok(match backend {
+        Backend::Redis => Arc::new(RedisStorage::new()?),
+        Backend::Memory => Arc::new(MemoryStorage::new()),
+        Backend::Sqlite => {
+            let path = env::var("SQLITE_PATH").unwrap_or_else(|_| String::from(SQLITE_PATH));
+            Arc::new(SqliteStorage::open(&path)?)
+        }
+    })
+}
+
+// Returns the number of snapshots of the crawls of a domain to keep.
+pub fn snapshots_kept() -> usize {
+    env::var("SNAPSHOTS_KEPT")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(SNAPSHOTS_KEPT)
+        .max(1)
+}
+
+// Returns what is known about a url whose information was not stored.
+pub fn bare_page(url: String) -> PageJson {
+    PageJson {
+        url,
+        depth: None,
+        sources: vec![],
+        referrers: vec![],
+        sitemap: None,
+        fetch: None,
+        metrics: None,
+        change: None,
+    }
+}
+
+// Serializes a value before its insertion in the storage.
+pub fn to_json<T: Serialize>(value: &T) -> Result<String> {
+    serde_json::to_string(value).map_err(|_| CrawlError::new(ErrorType::ScrapError))
+}
+
+// Returns the current time in seconds since the Unix epoch.
+pub fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::json::{FetchJson, JobState, ProgressJson};
+    use crate::options::CrawlOptions;
+
+    // Runs the same operations on a backend and checks what it returns.
+    fn check_storage(storage: &dyn Storage) {
+        let name = "example.com";
+        assert_eq!(storage.get_set(name), None);
+        assert_eq!(storage.get_len(name), None);
+
+        let mut page = bare_page(String::from("https://example.com/"));
+        page.fetch = Some(FetchJson {
+            status: Some(200),
+            error: None,
+            content_type: None,
+            response_time_ms: 10,
+            size: 0,
+            etag: None,
+            last_modified: None,
+            content_hash: None,
+        });
+        let other = bare_page(String::from("https://example.com/about"));
+        storage
+            .set_pages(name, &[page.clone(), other.clone()])
+            .unwrap();
+        assert_eq!(storage.get_len(name), Some((name, 2)));
+        let mut pages = storage.get_pages(name).unwrap();
+        pages.sort_by(|a, b| a.url.cmp(&b.url));
+        assert_eq!(pages, vec![page.clone(), other]);
+
+        storage.set_pages(name, &[page.clone()]).unwrap();
+        assert_eq!(storage.get_pages(name), Some(vec![page.clone()]));
+
+        let blocked: HashSet<String> = vec![String::from("https://example.com/private")]
+            .into_iter()
+            .collect();
+        storage.set_blocked(name, blocked.clone()).unwrap();
+        assert_eq!(storage.get_blocked(name), blocked);
+        storage.set_blocked(name, HashSet::new()).unwrap();
+        assert!(storage.get_blocked(name).is_empty());
+
+        let mut graph = HashMap::new();
+        graph.insert(page.url.clone(), vec![]);
+        storage.set_graph(name, &graph).unwrap();
+        assert_eq!(storage.get_graph(name), Some(graph));
+
+        storage.set_crawled_at(name, 1500000000).unwrap();
+        assert_eq!(storage.get_crawled_at(name), Some(1500000000));
+        assert_eq!(storage.get_rules(name), None);
+        assert_eq!(storage.get_options(name), None);
+        let options = CrawlOptions::default();
+        storage.set_options(name, &options).unwrap();
+        assert_eq!(storage.get_options(name), Some(options));
+
+        assert!(storage.lock_refresh(name).unwrap());
+        assert!(!storage.lock_refresh(name).unwrap());
+        storage.unlock_refresh(name).unwrap();
+        assert!(storage.lock_refresh(name).unwrap());
+
+        for crawled_at in 0..snapshots_kept() as u64 + 2 {
+            storage
+                .add_snapshot(name, crawled_at, &[page.clone()])
+                .unwrap();
+        }
+        let snapshots = storage.get_snapshots(name);
+        assert_eq!(snapshots.len(), snapshots_kept());
+        assert_eq!(snapshots.first(), Some(&2));
+        assert_eq!(storage.get_snapshot(name, 0), None);
+        assert_eq!(
+            storage.get_snapshot(name, 2).unwrap()[&page.url].status,
+            Some(200)
+        );
+
+        let checkpoint = Checkpoint {
+            depth: 1,
+            frontier: vec![],
+            visited: HashMap::new(),
+            blocked: HashSet::new(),
+            graph: HashMap::new(),
+        };
+        storage.set_checkpoint("1f-2", &checkpoint).unwrap();
+        assert_eq!(storage.get_checkpoint("1f-2").unwrap().depth, 1);
+        assert!(storage.get_checkpoint("1f-3").is_none());
+        storage.delete_checkpoint("1f-2").unwrap();
+        assert!(storage.get_checkpoint("1f-2").is_none());
+
+        let mut job = JobJson {
+            id: String::from("1f-2"),
+            state: JobState::Running,
+            url: page.url.clone(),
+            options: CrawlOptions::default(),
+            progress: ProgressJson::default(),
+            refresh: false,
+            result: None,
+            error: None,
+        };
+        storage.set_job(&job).unwrap();
+        assert!(storage.get_active_jobs().unwrap().contains("1f-2"));
+        job.state = JobState::Done;
+        storage.set_job(&job).unwrap();
+        assert!(storage.get_active_jobs().unwrap().is_empty());
+        assert_eq!(storage.get_job("1f-2").unwrap().state, JobState::Done);
+        job.state = JobState::Queued;
+        storage.set_job(&job).unwrap();
+        storage.delete_job("1f-2").unwrap();
+        assert!(storage.get_job("1f-2").is_none());
+        assert!(storage.get_active_jobs().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_memory_storage() {
+        check_storage(&MemoryStorage::new());
+    }
+
+    #[test]
+    fn test_sqlite_storage() {
+        check_storage(&SqliteStorage::open(":memory:").unwrap());
+    }
+}